@@ -1,5 +1,11 @@
+#[cfg(feature = "app")]
 const HELPER_FOLDER: &str = "helper-win32";
 
+/// Nothing to generate for `volumize-server` on its own.
+#[cfg(not(feature = "app"))]
+fn main() {}
+
+#[cfg(feature = "app")]
 fn main() {
     let target_os = std::env::var_os("CARGO_CFG_TARGET_OS").unwrap();
 
@@ -50,6 +56,7 @@ fn main() {
 /// Building the Tauri config myself.
 ///
 /// Because Tauri doesn't expose the config parsing to build scripts.
+#[cfg(feature = "app")]
 fn build_tauri_config() -> tauri::utils::config::Config {
     use std::env::{current_dir, var};
     use tauri::utils::{
@@ -71,6 +78,7 @@ fn build_tauri_config() -> tauri::utils::config::Config {
     config
 }
 
+#[cfg(feature = "app")]
 fn format_child_output(input: &str) -> String {
     fn get_version(input: &str) -> Option<String> {
         let first_part = input.split_once("@")?.1;
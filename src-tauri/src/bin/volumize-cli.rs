@@ -0,0 +1,557 @@
+//! Command-line client for a running Volumize server.
+//!
+//! Talks the same WebSocket protocol as the mobile app, so anything the app
+//! can do is scriptable from keyboard shortcuts or cron jobs.
+use std::{error::Error, process::ExitCode, time::Duration};
+
+use futures_util::{SinkExt, StreamExt};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use tokio::{net::TcpStream, time::timeout};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+use shared_types::{AppIdentifier, AudioApplication, AudioDevice};
+use volumize_lib::{
+    server::{
+        service_discovery::{discover_server, discover_servers},
+        PROTOCOL_VERSION,
+    },
+    types::shared::{ChangeType, Identifier, UpdateChange, SERVER_MOVED_EVENT_NAME},
+};
+
+const USAGE: &str = "\
+Usage: volumize-cli [--server <host:port>] [--json] <command>
+
+Commands:
+  discover                        List servers on the local network
+  devices                         List playback devices
+  apps [<device>]                 List applications, optionally of one device
+  get <device|app> <target>       Print volume and mute state
+  set <device|app> <target> <%>   Set volume, 0 to 100
+  adjust <device|app> <target> <±%>
+                                  Change volume relative to the current value
+  mute <device|app> <target>
+  unmute <device|app> <target>
+  watch                           Print live updates until interrupted
+
+Targets are ids (device id, process id) or names, matched case-insensitively.
+An application name matching several processes applies to all of them.
+
+Options:
+  --server <host:port>   Server to connect to, defaults to $VOLUMIZE_SERVER
+                         and falls back to discovery
+  --json                 Print JSON instead of text
+  -h, --help             Print this help";
+
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+const DISCOVERY_WINDOW: Duration = Duration::from_secs(3);
+
+type CliResult<T> = Result<T, Box<dyn Error>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Device,
+    App,
+}
+
+#[derive(Debug)]
+enum Command {
+    Discover,
+    Devices,
+    Apps(Option<String>),
+    Get(Kind, String),
+    Set(Kind, String, f32),
+    Adjust(Kind, String, f32),
+    Mute(Kind, String, bool),
+    Watch,
+}
+
+struct Options {
+    server: Option<String>,
+    json: bool,
+    command: Command,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1).collect()) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match run(options).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// ============================ Arguments ============================
+/// `Ok(None)` when help was asked for.
+fn parse_args(args: Vec<String>) -> Result<Option<Options>, String> {
+    let mut server = std::env::var("VOLUMIZE_SERVER").ok();
+    let mut json = false;
+    let mut positional = vec![];
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--json" => json = true,
+            "--server" => server = Some(args.next().ok_or("--server needs an address")?),
+            _ => positional.push(arg),
+        }
+    }
+
+    let positional: Vec<&str> = positional.iter().map(|arg| arg.as_str()).collect();
+    let command = match positional.as_slice() {
+        ["discover"] => Command::Discover,
+        ["devices"] => Command::Devices,
+        ["apps"] => Command::Apps(None),
+        ["apps", device] => Command::Apps(Some(device.to_string())),
+        ["get", kind, target] => Command::Get(parse_kind(kind)?, target.to_string()),
+        ["set", kind, target, volume] => Command::Set(
+            parse_kind(kind)?,
+            target.to_string(),
+            parse_percent(volume)?,
+        ),
+        ["adjust", kind, target, delta] => {
+            Command::Adjust(parse_kind(kind)?, target.to_string(), parse_percent(delta)?)
+        }
+        ["mute", kind, target] => Command::Mute(parse_kind(kind)?, target.to_string(), true),
+        ["unmute", kind, target] => Command::Mute(parse_kind(kind)?, target.to_string(), false),
+        ["watch"] => Command::Watch,
+        [] => return Err("Missing command".into()),
+        _ => return Err(format!("Invalid command: {}", positional.join(" "))),
+    };
+
+    Ok(Some(Options {
+        server,
+        json,
+        command,
+    }))
+}
+
+fn parse_kind(kind: &str) -> Result<Kind, String> {
+    match kind {
+        "device" => Ok(Kind::Device),
+        "app" => Ok(Kind::App),
+        _ => Err(format!("Expected `device` or `app`, got: {}", kind)),
+    }
+}
+
+/// Percent on the command line, `VolumePercent` (0.0 - 1.0) on the wire.
+fn parse_percent(value: &str) -> Result<f32, String> {
+    value
+        .trim_end_matches('%')
+        .parse::<f32>()
+        .map(|percent| percent / 100.0)
+        .map_err(|_| format!("Invalid volume: {}", value))
+}
+
+// ============================= Commands ============================
+async fn run(options: Options) -> CliResult<()> {
+    if let Command::Discover = options.command {
+        let servers = discover_servers(DISCOVERY_WINDOW).await;
+        if servers.is_empty() {
+            return Err("No server found".into());
+        }
+
+        return output(options.json, &servers, |servers| {
+            for server in servers {
+                let name = server.name.as_deref().unwrap_or("(unnamed)");
+                let version = server.version.as_deref().unwrap_or("?");
+                let os = server.os.as_deref().unwrap_or("?");
+                println!(
+                    "{}  {}  (v{}, {})",
+                    name,
+                    server.addresses.join(", "),
+                    version,
+                    os
+                );
+            }
+        });
+    }
+
+    let address = match options.server {
+        Some(address) => address,
+        None => discover_server().await?,
+    };
+    let mut client = Client::connect(&address).await?;
+    let json = options.json;
+
+    match options.command {
+        Command::Discover => Ok(()),
+        Command::Devices => {
+            let devices = client.devices().await?;
+            output(json, &devices, |devices| {
+                devices.iter().for_each(print_device)
+            })
+        }
+        Command::Apps(device) => {
+            let devices = match device {
+                Some(target) => vec![client.resolve_device(&target).await?],
+                None => client.devices().await?,
+            };
+
+            let mut applications = vec![];
+            for device in devices {
+                applications.extend(client.device_applications(&device.id).await?);
+            }
+            output(json, &applications, |apps| {
+                apps.iter().for_each(print_application)
+            })
+        }
+        Command::Get(Kind::Device, target) => {
+            let device = client.resolve_device(&target).await?;
+            output(json, &device, print_device)
+        }
+        Command::Get(Kind::App, target) => {
+            let applications = client.resolve_applications(&target).await?;
+            output(json, &applications, |apps| {
+                apps.iter().for_each(print_application)
+            })
+        }
+        Command::Set(kind, target, volume) => client.set_volume(kind, &target, |_| volume).await,
+        Command::Adjust(kind, target, delta) => {
+            client
+                .set_volume(kind, &target, |current| current + delta)
+                .await
+        }
+        Command::Mute(kind, target, mute) => client.set_mute(kind, &target, mute).await,
+        Command::Watch => watch(client, &address, json).await,
+    }
+}
+
+/// Prints updates until the connection closes, follows the server when it changes port.
+async fn watch(mut client: Client, address: &str, json: bool) -> CliResult<()> {
+    let mut address = address.to_string();
+
+    loop {
+        let event = match client.next_event().await? {
+            Some(event) => event,
+            None => return Ok(()),
+        };
+
+        if event["event"] == SERVER_MOVED_EVENT_NAME {
+            let port = event["payload"]["port"]
+                .as_u64()
+                .ok_or("Invalid server_moved event")?;
+            let host = split_address(&address).0.to_string();
+            address = format!("{}:{}", host, port);
+
+            eprintln!("Server moved to {}, reconnecting", address);
+            client = Client::connect(&address).await?;
+            continue;
+        }
+
+        let update: UpdateChange = match serde_json::from_value(event["payload"].clone()) {
+            Ok(update) => update,
+            Err(_) => continue,
+        };
+        match json {
+            true => println!("{}", serde_json::to_string(&update)?),
+            false => print_update(&update),
+        }
+    }
+}
+
+// ============================== Client =============================
+struct Client {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    next_request: u64,
+}
+
+impl Client {
+    async fn connect(address: &str) -> CliResult<Self> {
+        let (socket, _) = connect_async(websocket_url(address)).await?;
+        let mut client = Self {
+            socket,
+            next_request: 0,
+        };
+
+        // Shows up by name in the server's client list.
+        let params = json!({
+            "device_name": "volumize-cli",
+            "protocol_version": PROTOCOL_VERSION,
+        });
+        client.request::<Value>("hello", params).await?;
+        Ok(client)
+    }
+
+    /// Sends `{ name: params }` and waits for the response carrying the same request id.
+    /// - Events that arrive in the meantime are skipped.
+    async fn request<T: DeserializeOwned>(
+        &mut self,
+        name: &str,
+        mut params: Value,
+    ) -> CliResult<T> {
+        self.next_request += 1;
+        let request_id = format!("cli-{}", self.next_request);
+        params["request_id"] = json!(request_id);
+
+        let message = json!({ name: params }).to_string();
+        self.socket.send(Message::from(message)).await?;
+
+        // The server only logs failed commands, an unknown id never gets an answer.
+        match timeout(RESPONSE_TIMEOUT, self.response(&request_id)).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("No response to `{}`, the target may not exist", name).into()),
+        }
+    }
+
+    async fn response<T: DeserializeOwned>(&mut self, request_id: &str) -> CliResult<T> {
+        while let Some(message) = self.socket.next().await {
+            let text = match message? {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+
+            let value: Value = serde_json::from_str(text.as_str())?;
+            if value["type"] == request_id {
+                return Ok(serde_json::from_value(value["data"].clone())?);
+            }
+        }
+        Err("Connection closed".into())
+    }
+
+    /// Next `{ "event": name, "payload": data }` message, `None` once the server closes.
+    async fn next_event(&mut self) -> CliResult<Option<Value>> {
+        while let Some(message) = self.socket.next().await {
+            let text = match message? {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+
+            let value: Value = serde_json::from_str(text.as_str())?;
+            if value.get("event").is_some() {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn devices(&mut self) -> CliResult<Vec<AudioDevice>> {
+        self.request("get_playback_devices", json!({})).await
+    }
+
+    async fn device_applications(&mut self, device_id: &str) -> CliResult<Vec<AudioApplication>> {
+        let ids: Vec<AppIdentifier> = self
+            .request("get_device_applications", json!({ "id": device_id }))
+            .await?;
+
+        let mut applications = vec![];
+        for id in ids {
+            // Sessions can expire between the two requests.
+            if let Ok(application) = self.request("get_application", json!({ "id": id })).await {
+                applications.push(application);
+            }
+        }
+        Ok(applications)
+    }
+
+    async fn applications(&mut self) -> CliResult<Vec<AudioApplication>> {
+        let mut applications = vec![];
+        for device in self.devices().await? {
+            applications.extend(self.device_applications(&device.id).await?);
+        }
+        Ok(applications)
+    }
+
+    /// Exact id, then exact name, then a unique partial name.
+    async fn resolve_device(&mut self, target: &str) -> CliResult<AudioDevice> {
+        let devices = self.devices().await?;
+        let target_lower = target.to_lowercase();
+
+        let names = |device: &AudioDevice| {
+            [
+                device.name.to_lowercase(),
+                device.friendly_name.to_lowercase(),
+            ]
+        };
+
+        if let Some(device) = devices.iter().find(|device| device.id == target) {
+            return Ok(device.clone());
+        }
+        if let Some(device) = devices
+            .iter()
+            .find(|device| names(device).contains(&target_lower))
+        {
+            return Ok(device.clone());
+        }
+
+        let partial: Vec<&AudioDevice> = devices
+            .iter()
+            .filter(|device| {
+                names(device)
+                    .iter()
+                    .any(|name| name.contains(&target_lower))
+            })
+            .collect();
+        match partial.as_slice() {
+            [device] => Ok((*device).clone()),
+            [] => Err(format!("No device matches: {}", target).into()),
+            _ => Err(format!("Several devices match: {}", target).into()),
+        }
+    }
+
+    /// A process id, or every application whose name matches, `.exe` is optional.
+    async fn resolve_applications(&mut self, target: &str) -> CliResult<Vec<AudioApplication>> {
+        let applications = self.applications().await?;
+        let target_lower = target.to_lowercase();
+        let pid = target.parse::<AppIdentifier>().ok();
+
+        let matches: Vec<AudioApplication> = applications
+            .into_iter()
+            .filter(|app| {
+                let name = app.process.name.to_lowercase();
+                Some(app.process.id) == pid
+                    || name == target_lower
+                    || name.trim_end_matches(".exe") == target_lower
+            })
+            .collect();
+
+        match matches.is_empty() {
+            true => Err(format!("No application matches: {}", target).into()),
+            false => Ok(matches),
+        }
+    }
+
+    /// Sets the volume of every resolved target, `volume` maps the current value to the new one.
+    async fn set_volume(
+        &mut self,
+        kind: Kind,
+        target: &str,
+        volume: impl Fn(f32) -> f32,
+    ) -> CliResult<()> {
+        let targets: Vec<(Value, f32)> = match kind {
+            Kind::Device => {
+                let device = self.resolve_device(target).await?;
+                vec![(json!(device.id), device.volume.current)]
+            }
+            Kind::App => self
+                .resolve_applications(target)
+                .await?
+                .into_iter()
+                .map(|app| (json!(app.process.id), app.volume.current))
+                .collect(),
+        };
+
+        let name = match kind {
+            Kind::Device => "device_set_volume",
+            Kind::App => "application_set_volume",
+        };
+        for (id, current) in targets {
+            let new_volume = volume(current).clamp(0.0, 1.0);
+            self.request::<Value>(name, json!({ "id": id, "volume": new_volume }))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn set_mute(&mut self, kind: Kind, target: &str, mute: bool) -> CliResult<()> {
+        let ids: Vec<Value> = match kind {
+            Kind::Device => vec![json!(self.resolve_device(target).await?.id)],
+            Kind::App => self
+                .resolve_applications(target)
+                .await?
+                .into_iter()
+                .map(|app| json!(app.process.id))
+                .collect(),
+        };
+
+        let name = match (kind, mute) {
+            (Kind::Device, true) => "device_mute",
+            (Kind::Device, false) => "device_unmute",
+            (Kind::App, true) => "application_mute",
+            (Kind::App, false) => "application_unmute",
+        };
+        for id in ids {
+            self.request::<Value>(name, json!({ "id": id })).await?;
+        }
+        Ok(())
+    }
+}
+
+// ============================== Output =============================
+/// `--json` prints `value` as is, otherwise `text` formats it.
+fn output<T: serde::Serialize>(json: bool, value: &T, text: impl FnOnce(&T)) -> CliResult<()> {
+    match json {
+        true => println!("{}", serde_json::to_string_pretty(value)?),
+        false => text(value),
+    }
+    Ok(())
+}
+
+fn percent(volume: f32) -> String {
+    format!("{:.0}%", volume * 100.0)
+}
+
+fn print_device(device: &AudioDevice) {
+    println!(
+        "{}\t{}\t{}{}{}",
+        device.id,
+        device.friendly_name,
+        percent(device.volume.current),
+        if device.volume.muted { " muted" } else { "" },
+        if device.is_default { " (default)" } else { "" },
+    );
+}
+
+fn print_application(app: &AudioApplication) {
+    println!(
+        "{}\t{}\t{}{}",
+        app.process.id,
+        app.process.name,
+        percent(app.volume.current),
+        if app.volume.muted { " muted" } else { "" },
+    );
+}
+
+fn print_update(update: &UpdateChange) {
+    let id = match &update.id {
+        Identifier::App(pid) => format!("app {}", pid),
+        Identifier::Device(id) => format!("device {}", id),
+    };
+
+    match &update.change {
+        ChangeType::AudioVolume { volume, mute } => println!(
+            "{}: volume {}{}",
+            id,
+            percent(*volume),
+            if *mute { " muted" } else { "" }
+        ),
+        ChangeType::IconPathChange { path } => println!("{}: icon {}", id, path),
+        ChangeType::StateChange { state } => println!("{}: {:?}", id, state),
+        ChangeType::NameChange { name } => println!("{}: renamed to {}", id, name),
+    }
+}
+
+// ============================= Address =============================
+/// Discovery returns `ip:port`, IPv6 hosts need brackets in a URL.
+fn websocket_url(address: &str) -> String {
+    match split_address(address) {
+        (host, Some(port)) if host.contains(':') && !host.starts_with('[') => {
+            format!("ws://[{}]:{}", host, port)
+        }
+        _ => format!("ws://{}", address),
+    }
+}
+
+fn split_address(address: &str) -> (&str, Option<&str>) {
+    match address.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => (host, Some(port)),
+        _ => (address, None),
+    }
+}
@@ -0,0 +1,76 @@
+//! Volumize server without the desktop window or tray.
+//!
+//! Runs the volume thread, WebSocket server, service registration and the
+//! local control endpoint from the saved settings until SIGINT or SIGTERM.
+//! Changes to the settings file are applied while running.
+//!
+//! Takes `--config-dir <path>` and `--portable`, see `resolve_config_dir`.
+use std::{
+    process::ExitCode,
+    sync::{mpsc, Arc},
+};
+
+use volumize_lib::{
+    server::{
+        ipc::start_ipc_server,
+        rules::spawn_rule_clock,
+        service_register::start_service_register,
+        settings::spawn_settings_watcher,
+        start_websocket_server,
+        volume_control::{spawn_update_thread, spawn_volume_thread},
+        NoEvents, ServerContext,
+    },
+    types::{
+        shared::UpdateChange,
+        storage::{resolve_config_dir, Storage},
+    },
+};
+
+fn main() -> ExitCode {
+    let dir = resolve_config_dir(std::env::args().skip(1));
+    println!("Config directory: {}", dir.display());
+
+    let context = ServerContext::new(Storage::new(dir), Arc::new(NoEvents));
+
+    context.storage.load();
+    let settings = context.storage.get();
+
+    let (tx, rx) = mpsc::channel::<UpdateChange>();
+    spawn_volume_thread(&context, tx);
+    spawn_update_thread(&context, rx);
+
+    match start_websocket_server(&settings, &context) {
+        Ok(addrs) => println!("WebSocket server listening on {:?}", addrs),
+        Err(e) => {
+            eprintln!("Failed to start WebSocket server: {}", e);
+            context.shutdown();
+            return ExitCode::FAILURE;
+        }
+    }
+
+    start_service_register(&context, settings.duration);
+
+    match start_ipc_server(&context) {
+        Ok(endpoint) => println!("Local control endpoint listening on {}", endpoint),
+        Err(e) => eprintln!("Failed to start local control endpoint: {}", e),
+    }
+
+    spawn_settings_watcher(&context);
+    spawn_rule_clock(&context);
+
+    // The handler runs on its own thread, shutdown happens back on this one.
+    let (stop_tx, stop_rx) = mpsc::channel::<()>();
+    if let Err(e) = ctrlc::set_handler(move || {
+        let _ = stop_tx.send(());
+    }) {
+        eprintln!("Failed to set signal handler: {}", e);
+        context.shutdown();
+        return ExitCode::FAILURE;
+    }
+
+    let _ = stop_rx.recv();
+    println!("Signal received, shutting down...");
+    context.shutdown();
+
+    ExitCode::SUCCESS
+}
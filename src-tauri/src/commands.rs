@@ -1,9 +1,22 @@
-use tauri::State;
+use std::{path::Path, time::Duration};
+
+use tauri::{AppHandle, Manager, State};
 use tokio::sync::mpsc::unbounded_channel;
 
 use crate::{
-    server::service_discovery,
-    types::volume::{VolumeCommand, VolumeCommandSender},
+    server::{
+        bundle::{self, ImportPreview},
+        profiles, rules,
+        service_discovery::{self, DiscoveredServer},
+        settings, ClientInfo, DiscoveryStatus, ServerContext, ServiceDiscovery,
+        WebSocketServerState,
+    },
+    types::{
+        profiles::{Profile, ProfileReport, Profiles},
+        rules::Rule,
+        storage::{LoadReport, Settings, Storage},
+        volume::{VolumeCommand, VolumeCommandSender},
+    },
 };
 
 use shared_types::{AppIdentifier, AudioApplication, AudioDevice, DeviceIdentifier, VolumePercent};
@@ -196,8 +209,151 @@ pub async fn get_device_applications(
     Err(())
 }
 
+// ============================ Clients ============================
+#[tauri::command]
+pub async fn get_connected_clients(
+    state: State<'_, WebSocketServerState>,
+) -> Result<Vec<ClientInfo>, ()> {
+    Ok(state.list_clients().await)
+}
+
+#[tauri::command]
+pub async fn disconnect_client(
+    id: String,
+    state: State<'_, WebSocketServerState>,
+) -> Result<ClientInfo, String> {
+    state.disconnect_client(&id).await
+}
+
+// ============================ Server =============================
+#[tauri::command]
+pub fn get_server_addresses(state: State<WebSocketServerState>) -> Vec<String> {
+    state
+        .local_addresses()
+        .iter()
+        .map(|addr| addr.to_string())
+        .collect()
+}
+
+/// Moves the WebSocket server to `port`, clients are told the new port.
+/// - Runs on the main thread, the server state is locked synchronously.
+#[tauri::command]
+pub fn change_server_port(port: u16, app: AppHandle) -> Result<Vec<String>, String> {
+    let context = app.state::<ServerContext>();
+    let mut settings = context.storage.get();
+    settings.port_address = port;
+
+    settings::update_settings(&context, settings)?;
+
+    let addresses = context.websocket.local_addresses();
+    Ok(addresses.iter().map(|addr| addr.to_string()).collect())
+}
+
+#[tauri::command]
+pub fn get_discovery_status(state: State<ServiceDiscovery>) -> DiscoveryStatus {
+    state.status()
+}
+
+// =========================== Settings ============================
+#[tauri::command]
+pub fn get_settings(state: State<Storage>) -> Settings {
+    state.get()
+}
+
+/// Validates and applies every field, see `server::settings::update_settings`.
+/// - Runs on the main thread, the server state is locked synchronously.
+#[tauri::command]
+pub fn update_settings(settings: Settings, app: AppHandle) -> Result<Settings, String> {
+    let context = app.state::<ServerContext>();
+    settings::update_settings(&context, settings)
+}
+
+/// Problems found when the settings file was loaded at startup.
+#[tauri::command]
+pub fn get_settings_load_report(state: State<Storage>) -> LoadReport {
+    state.load_report()
+}
+
+// =========================== Profiles ============================
+#[tauri::command]
+pub fn list_profiles(state: State<Profiles>) -> Vec<String> {
+    state.names()
+}
+
+#[tauri::command]
+pub fn get_profile(name: String, state: State<Profiles>) -> Result<Option<Profile>, String> {
+    state.get(&name).map_err(|e| e.to_string())
+}
+
+/// Saves the current master and per-app volumes as `name`.
+#[tauri::command]
+pub async fn save_profile(name: String, app: AppHandle) -> Result<Profile, String> {
+    let context = app.state::<ServerContext>().inner().clone();
+    profiles::save_profile(&context, &name).await
+}
+
+#[tauri::command]
+pub async fn apply_profile(name: String, app: AppHandle) -> Result<ProfileReport, String> {
+    let context = app.state::<ServerContext>().inner().clone();
+    profiles::apply_profile(&context, &name).await
+}
+
+#[tauri::command]
+pub fn delete_profile(name: String, app: AppHandle) -> Result<(), String> {
+    let context = app.state::<ServerContext>();
+    profiles::delete_profile(&context, &name)
+}
+
+// ============================= Rules =============================
+#[tauri::command]
+pub fn list_rules(state: State<Storage>) -> Vec<Rule> {
+    state.get().rules
+}
+
+/// Adds or replaces a rule, see `server::rules::save_rule`.
+/// - Runs on the main thread, the server state is locked synchronously.
+#[tauri::command]
+pub fn save_rule(rule: Rule, app: AppHandle) -> Result<Rule, String> {
+    let context = app.state::<ServerContext>();
+    rules::save_rule(&context, rule)
+}
+
+#[tauri::command]
+pub fn delete_rule(id: String, app: AppHandle) -> Result<(), String> {
+    let context = app.state::<ServerContext>();
+    rules::delete_rule(&context, &id)
+}
+
+// ============================ Backup =============================
+/// Settings, profiles and remembered app volumes in one file, see `server::bundle`.
+#[tauri::command]
+pub fn export_bundle(path: String, app: AppHandle) -> Result<(), String> {
+    let context = app.state::<ServerContext>();
+    bundle::export_bundle(&context, Path::new(&path))
+}
+
+/// What `import_bundle` would change, nothing is applied.
+#[tauri::command]
+pub fn preview_import(path: String, app: AppHandle) -> Result<ImportPreview, String> {
+    let context = app.state::<ServerContext>();
+    bundle::preview_import(&context, Path::new(&path))
+}
+
+/// - Runs on the main thread, the server state is locked synchronously.
+#[tauri::command]
+pub fn import_bundle(path: String, app: AppHandle) -> Result<ImportPreview, String> {
+    let context = app.state::<ServerContext>();
+    bundle::import_bundle(&context, Path::new(&path))
+}
+
 // ========================= Miscellaneous =========================
 #[tauri::command]
 pub async fn discover_server_address() -> Option<String> {
     service_discovery::discover_server().await.ok()
 }
+
+/// Every server answering within a few seconds.
+#[tauri::command]
+pub async fn discover_servers() -> Vec<DiscoveredServer> {
+    service_discovery::discover_servers(Duration::from_secs(3)).await
+}
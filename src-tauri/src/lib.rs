@@ -1,10 +1,12 @@
 #![allow(dead_code)]
+#[cfg(feature = "app")]
 mod commands;
 mod platform;
-mod server;
-mod types;
+pub mod server;
+pub mod types;
 
 /// Entry point for the android/ios application.
+#[cfg(feature = "app")]
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn start_application() {
     std::panic::set_hook(Box::new(|info| {
@@ -17,7 +19,10 @@ pub fn start_application() {
         .plugin(tauri_plugin_websocket::init())
         .plugin(tauri_plugin_os::init())
         .plugin(tauri_plugin_opener::init())
-        .invoke_handler(tauri::generate_handler![commands::discover_server_address])
+        .invoke_handler(tauri::generate_handler![
+            commands::discover_server_address,
+            commands::discover_servers
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
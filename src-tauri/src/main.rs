@@ -9,10 +9,7 @@ mod types;
 
 use tauri::{AppHandle, Manager};
 
-use crate::{
-    server::{ServiceDiscovery, WebSocketServerState},
-    types::volume::VolumeCommandSender,
-};
+use crate::server::ServerContext;
 
 /// Entry point for the desktop application.
 /// - **NEVER** let the `lib.rs` code to touch any desktop functionality.
@@ -49,21 +46,5 @@ fn main() {
 }
 
 fn shutdown_background_threads(app_handle: &AppHandle) {
-    if let Err(e) = app_handle.state::<VolumeCommandSender>().shutdown() {
-        eprintln!("Volume thread shutdown error: {}", e);
-    }
-
-    let service_state = app_handle.state::<ServiceDiscovery>();
-    tauri::async_runtime::block_on(async {
-        if let Err(e) = service_state.shutdown().await {
-            eprintln!("Service thread shutdown error: {}", e);
-        }
-    });
-
-    let ws_state = app_handle.state::<WebSocketServerState>();
-    tauri::async_runtime::block_on(async {
-        if let Err(err) = ws_state.shutdown().await {
-            eprintln!("WebSocket server shutdown error: {}", err);
-        }
-    });
+    app_handle.state::<ServerContext>().shutdown();
 }
@@ -66,6 +66,10 @@ impl ComManager {
         })
     }
 
+    pub fn get_device_enumerator(&self) -> &IMMDeviceEnumerator {
+        &self.device_enumerator
+    }
+
     pub fn get_event_context(&self) -> &GUID {
         &self.event_context
     }
@@ -9,15 +9,20 @@ use std::{
 
 use windows::{
     core::{implement, Interface, Ref, Result as WinResult, BOOL, GUID, PCWSTR},
-    Win32::Media::Audio::{
-        AudioSessionDisconnectReason, AudioSessionState, AudioSessionStateActive,
-        AudioSessionStateExpired, AudioSessionStateInactive,
-        Endpoints::{
-            IAudioEndpointVolume, IAudioEndpointVolumeCallback, IAudioEndpointVolumeCallback_Impl,
+    Win32::{
+        Foundation::PROPERTYKEY,
+        Media::Audio::{
+            AudioSessionDisconnectReason, AudioSessionState, AudioSessionStateActive,
+            AudioSessionStateExpired, AudioSessionStateInactive, EDataFlow, ERole,
+            Endpoints::{
+                IAudioEndpointVolume, IAudioEndpointVolumeCallback,
+                IAudioEndpointVolumeCallback_Impl,
+            },
+            IAudioSessionControl, IAudioSessionControl2, IAudioSessionEvents,
+            IAudioSessionEvents_Impl, IAudioSessionManager2, IAudioSessionNotification,
+            IAudioSessionNotification_Impl, IMMDeviceEnumerator, IMMNotificationClient,
+            IMMNotificationClient_Impl, AUDIO_VOLUME_NOTIFICATION_DATA, DEVICE_STATE,
         },
-        IAudioSessionControl, IAudioSessionControl2, IAudioSessionEvents, IAudioSessionEvents_Impl,
-        IAudioSessionManager2, IAudioSessionNotification, IAudioSessionNotification_Impl,
-        AUDIO_VOLUME_NOTIFICATION_DATA,
     },
 };
 
@@ -176,6 +181,12 @@ impl IAudioSessionEvents_Impl for SessionEvents_Impl {
 
     fn OnStateChanged(&self, newstate: AudioSessionState) -> WinResult<()> {
         println!("OnStateChanges fired: {} | {:?}", self.name, newstate);
+        if newstate == AudioSessionStateActive {
+            let update =
+                UpdateChange::app_state_change(Identifier::App(self.pid), EntityState::Active);
+            let _ = self.sender.send(update);
+        }
+
         if self.is_expire_state(newstate) {
             self.cleanup_session();
 
@@ -283,8 +294,100 @@ impl IAudioSessionNotification_Impl for SessionNotification_Impl {
     }
 }
 
+/// Playback devices coming and going, and default device changes.
+#[implement(IMMNotificationClient)]
+struct DeviceNotification {
+    enumerator: IMMDeviceEnumerator,
+    needs_reinit: Arc<AtomicBool>,
+    sender: VolumeSender,
+}
+
+impl DeviceNotification {
+    /// Capture devices are reported here as well, only playback devices are sent on.
+    fn is_playback(&self, device_id: &PCWSTR) -> bool {
+        unsafe { self.enumerator.GetDevice(*device_id) }
+            .ok()
+            .and_then(|device| super::convert::get_direction(&device).ok())
+            .is_some_and(|info| info.edataflow == ComManager::E_DATAFLOW)
+    }
+
+    fn send_state(&self, device_id: &PCWSTR, state: EntityState) {
+        let id = super::util::pcwstr_to_string(device_id);
+        let _ = self.sender.send(UpdateChange::app_state_change(
+            Identifier::Device(id),
+            state,
+        ));
+    }
+}
+
+impl IMMNotificationClient_Impl for DeviceNotification_Impl {
+    fn OnDeviceStateChanged(
+        &self,
+        pwstrdeviceid: &PCWSTR,
+        dwnewstate: DEVICE_STATE,
+    ) -> WinResult<()> {
+        if !self.is_playback(pwstrdeviceid) {
+            return Ok(());
+        }
+
+        let state = match dwnewstate == ComManager::DEVICE_STATE_CONTEXT {
+            true => EntityState::Created,
+            false => EntityState::Disconnect,
+        };
+        self.send_state(pwstrdeviceid, state);
+
+        // Callbacks are only registered for the devices there were at the time.
+        self.needs_reinit.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn OnDeviceAdded(&self, _pwstrdeviceid: &PCWSTR) -> WinResult<()> {
+        Ok(())
+    }
+
+    fn OnDeviceRemoved(&self, _pwstrdeviceid: &PCWSTR) -> WinResult<()> {
+        Ok(())
+    }
+
+    fn OnDefaultDeviceChanged(
+        &self,
+        flow: EDataFlow,
+        role: ERole,
+        pwstrdefaultdeviceid: &PCWSTR,
+    ) -> WinResult<()> {
+        if flow == ComManager::E_DATAFLOW && role == ComManager::E_ROLE {
+            self.send_state(pwstrdefaultdeviceid, EntityState::Default);
+        }
+        Ok(())
+    }
+
+    fn OnPropertyValueChanged(&self, _pwstrdeviceid: &PCWSTR, _key: &PROPERTYKEY) -> WinResult<()> {
+        Ok(())
+    }
+}
+
 use super::com_scope::ComManager;
 
+type RDNotice = (IMMDeviceEnumerator, IMMNotificationClient);
+
+fn register_device_notification(
+    manager: &ComManager,
+    needs_reinit: Arc<AtomicBool>,
+    sender: VolumeSender,
+) -> WinResult<RDNotice> {
+    let enumerator = manager.get_device_enumerator();
+    let notification: IMMNotificationClient = DeviceNotification {
+        enumerator: enumerator.clone(),
+        needs_reinit,
+        sender,
+    }
+    .into();
+
+    unsafe { enumerator.RegisterEndpointNotificationCallback(&notification) }?;
+
+    Ok((enumerator.clone(), notification))
+}
+
 type RDevice = (IAudioEndpointVolume, IAudioEndpointVolumeCallback);
 
 fn register_device(
@@ -372,6 +475,7 @@ fn register_application_notification(
 }
 
 pub struct AudioMonitor {
+    device_notification: Option<RDNotice>,
     device_callback: Vec<RDevice>,
     session_notification: Vec<RSNotice>,
     sessions_application: Arc<Mutex<RAEvents>>,
@@ -383,6 +487,7 @@ pub struct AudioMonitor {
 impl AudioMonitor {
     pub fn new(sender: VolumeSender) -> Self {
         Self {
+            device_notification: None,
             device_callback: Default::default(),
             session_notification: Default::default(),
             sessions_application: Default::default(),
@@ -398,6 +503,12 @@ impl AudioMonitor {
 
         let mut callbacks_application = vec![];
 
+        let needs_reinit = self.needs_reinit.clone();
+        match register_device_notification(manager, needs_reinit, self.sender.clone()) {
+            Ok(notification) => self.device_notification = Some(notification),
+            Err(e) => eprintln!("Error registering device notification: {}", e),
+        };
+
         println!("\nMonitoring device volume changes...\n");
 
         for device_id in manager.get_all_device_id().unwrap_or_default() {
@@ -467,6 +578,10 @@ impl AudioMonitor {
     }
 
     pub fn unregister_callbacks(&mut self) {
+        if let Some((enumerator, notification)) = self.device_notification.take() {
+            let _ = unsafe { enumerator.UnregisterEndpointNotificationCallback(&notification) };
+        }
+
         // Remove while iterating.
         self.device_callback.retain(|(endpoint, sessions)| {
             let _ = unsafe { endpoint.UnregisterControlChangeNotify(sessions) };
@@ -0,0 +1,100 @@
+use std::{
+    io::{Error, ErrorKind, Result as IoResult},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// Resolves `Settings.bind_addresses` into the IPs to listen on.
+/// - An empty list listens on every IPv4 and IPv6 interface.
+/// - Entries are IP addresses (`0.0.0.0`, `::`, `192.168.1.10`) or interface names (`eth0`).
+/// - Link-local IPv6 addresses of an interface are skipped, they need a scope id to bind.
+pub fn resolve_bind_addresses(entries: &[String]) -> Result<Vec<IpAddr>, String> {
+    if entries.is_empty() {
+        return Ok(vec![
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        ]);
+    }
+
+    let interfaces = local_ip_address::list_afinet_netifas().unwrap_or_default();
+    let mut addresses = vec![];
+
+    for entry in entries.iter().map(|entry| entry.trim()) {
+        if let Ok(ip) = entry.parse::<IpAddr>() {
+            addresses.push(ip);
+            continue;
+        }
+
+        let before = addresses.len();
+        for (name, ip) in &interfaces {
+            if name == entry && !is_ipv6_link_local(ip) {
+                addresses.push(*ip);
+            }
+        }
+
+        if addresses.len() == before {
+            eprintln!("[resolve_bind_addresses] No address found for: {}", entry);
+        }
+    }
+
+    addresses.sort();
+    addresses.dedup();
+
+    match addresses.is_empty() {
+        true => Err(format!(
+            "None of the bind addresses could be resolved: {:?}",
+            entries
+        )),
+        false => Ok(addresses),
+    }
+}
+
+pub fn is_ipv6_link_local(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V6(ip) => (ip.segments()[0] & 0xffc0) == 0xfe80,
+        IpAddr::V4(_) => false,
+    }
+}
+
+/// Port related errors are worth retrying on another port.
+/// - Windows reports reserved ports as permission denied.
+pub fn is_port_error(error: &Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::AddrInUse | ErrorKind::PermissionDenied
+    )
+}
+
+/// IPv6 sockets are IPv6 only, the IPv4 address is bound by its own socket.
+pub fn bind_socket(addr: SocketAddr, kind: Type) -> IoResult<Socket> {
+    let protocol = match kind {
+        Type::DGRAM => Protocol::UDP,
+        _ => Protocol::TCP,
+    };
+
+    let socket = Socket::new(Domain::for_address(addr), kind, Some(protocol))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+
+    if kind == Type::DGRAM {
+        let _ = socket.set_reuse_address(true);
+        #[cfg(unix)]
+        let _ = socket.set_reuse_port(true);
+    } else {
+        // Same as std: allow rebinding while old connections linger in TIME_WAIT.
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+    }
+
+    socket.bind(&addr.into())?;
+    socket.set_nonblocking(true)?; // Nonblocking for Tokio
+    Ok(socket)
+}
+
+pub fn tcp_listener(addr: SocketAddr) -> IoResult<std::net::TcpListener> {
+    let socket = bind_socket(addr, Type::STREAM)?;
+    socket.listen(128)?;
+    Ok(socket.into())
+}
@@ -0,0 +1,395 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::types::{
+    app_volumes::SavedVolume,
+    profiles::Profile,
+    rules::ActionTarget,
+    storage::{parse_settings, write_atomic, Settings},
+};
+
+use super::{profiles::check_profile_name, settings::update_settings, ServerContext};
+
+/// Bumped when a field changes meaning, new optional fields don't need it.
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// Everything needed to set up the server on another machine, kept in one JSON file.
+/// - `settings` is kept as JSON so older settings versions are migrated on import.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Bundle {
+    pub version: u32,
+    /// Unix seconds.
+    pub created_at: u64,
+    pub settings: Value,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    #[serde(default)]
+    pub app_volumes: HashMap<String, SavedVolume>,
+}
+
+/// What importing a bundle changes, shown before anything is applied.
+/// - Profiles and app volumes are merged, nothing already here is removed.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ImportPreview {
+    /// Names of the settings that differ from the ones in effect.
+    pub settings_changed: Vec<String>,
+    pub profiles_added: Vec<String>,
+    pub profiles_replaced: Vec<String>,
+    pub app_volumes_added: usize,
+    pub app_volumes_replaced: usize,
+    /// Things to check after importing, the bundle applies as it is.
+    pub warnings: Vec<String>,
+}
+
+/// Writes the settings, profiles and remembered app volumes to `path`.
+pub fn export_bundle(context: &ServerContext, path: &Path) -> Result<(), String> {
+    let created_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0);
+
+    let bundle = Bundle {
+        version: BUNDLE_VERSION,
+        created_at,
+        settings: serde_json::to_value(context.storage.get()).map_err(|e| e.to_string())?,
+        profiles: context.profiles.all().map_err(|e| e.to_string())?,
+        app_volumes: context.app_volumes.all(),
+    };
+
+    let data = serde_json::to_vec_pretty(&bundle).map_err(|e| e.to_string())?;
+    write_atomic(path, &data).map_err(|e| e.to_string())
+}
+
+/// Reads and validates the bundle at `path`, nothing is applied.
+pub fn preview_import(context: &ServerContext, path: &Path) -> Result<ImportPreview, String> {
+    let (settings, bundle) = read_bundle(path)?;
+    preview(context, &settings, &bundle)
+}
+
+/// Applies the bundle at `path`, returns what changed.
+/// - Everything is validated first, a bundle that doesn't validate changes nothing.
+/// - Settings are applied last through `update_settings`, profiles and app volumes
+///   stay imported when the server can't be moved.
+/// - The server keeps its own `server_id`, two machines shouldn't share one.
+/// - Blocks on the server locks, not to be called from the async runtime.
+pub fn import_bundle(context: &ServerContext, path: &Path) -> Result<ImportPreview, String> {
+    let (settings, bundle) = read_bundle(path)?;
+    let preview = preview(context, &settings, &bundle)?;
+
+    for profile in bundle.profiles.into_values() {
+        context
+            .profiles
+            .insert(profile)
+            .map_err(|e| e.to_string())?;
+    }
+    if !preview.profiles_added.is_empty() || !preview.profiles_replaced.is_empty() {
+        context.events.profiles_changed(&context.profiles.names());
+    }
+
+    for (app_path, value) in &bundle.app_volumes {
+        context.app_volumes.set(app_path, *value);
+    }
+    context.app_volumes.save().map_err(|e| e.to_string())?;
+
+    update_settings(context, settings)?;
+    Ok(preview)
+}
+
+/// Checks the bundle version and every part that can be checked before applying it.
+fn read_bundle(path: &Path) -> Result<(Settings, Bundle), String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    let bundle: Bundle = serde_json::from_slice(&bytes).map_err(|e| e.to_string())?;
+
+    if bundle.version == 0 || bundle.version > BUNDLE_VERSION {
+        return Err(format!("Unsupported bundle version ({})", bundle.version));
+    }
+
+    let settings = parse_settings(bundle.settings.clone())?;
+    settings.validate()?;
+
+    for (name, profile) in &bundle.profiles {
+        if *name != profile.name {
+            return Err(format!("Profile \"{}\" is stored under another name", name));
+        }
+        check_profile_name(name.trim())?;
+        let entries = profile.devices.iter().chain(&profile.apps);
+        if let Some(entry) = entries.into_iter().find(|entry| !is_volume(entry.volume)) {
+            return Err(format!(
+                "Profile \"{}\": invalid volume for {}",
+                name, entry.name
+            ));
+        }
+    }
+
+    if let Some((app_path, _)) = bundle
+        .app_volumes
+        .iter()
+        .find(|(app_path, saved)| app_path.is_empty() || !is_volume(saved.volume))
+    {
+        return Err(format!("Invalid remembered volume for \"{}\"", app_path));
+    }
+
+    Ok((settings, bundle))
+}
+
+fn is_volume(volume: f32) -> bool {
+    (0.0..=1.0).contains(&volume)
+}
+
+fn preview(
+    context: &ServerContext,
+    settings: &Settings,
+    bundle: &Bundle,
+) -> Result<ImportPreview, String> {
+    let profiles = context.profiles.all().map_err(|e| e.to_string())?;
+    diff(
+        &context.storage.get(),
+        &profiles,
+        &context.app_volumes.all(),
+        settings,
+        bundle,
+    )
+}
+
+/// What changes when `settings` and `bundle` replace the `current` ones.
+fn diff(
+    current: &Settings,
+    profiles: &BTreeMap<String, Profile>,
+    app_volumes: &HashMap<String, SavedVolume>,
+    settings: &Settings,
+    bundle: &Bundle,
+) -> Result<ImportPreview, String> {
+    let mut preview = ImportPreview::default();
+
+    let current = serde_json::to_value(current).map_err(|e| e.to_string())?;
+    let new = serde_json::to_value(settings).map_err(|e| e.to_string())?;
+    if let (Value::Object(current), Value::Object(new)) = (current, new) {
+        preview.settings_changed = new
+            .iter()
+            .filter(|(key, _)| !matches!(key.as_str(), "version" | "server_id"))
+            .filter(|(key, value)| current.get(key.as_str()) != Some(value))
+            .map(|(key, _)| key.clone())
+            .collect();
+    }
+
+    for name in bundle.profiles.keys() {
+        match profiles.contains_key(name) {
+            true => preview.profiles_replaced.push(name.clone()),
+            false => preview.profiles_added.push(name.clone()),
+        }
+    }
+
+    for (app_path, value) in &bundle.app_volumes {
+        match app_volumes.get(app_path) {
+            Some(saved) if saved == value => {}
+            Some(_) => preview.app_volumes_replaced += 1,
+            None => preview.app_volumes_added += 1,
+        }
+    }
+
+    // Device ids and pids written into a command only mean something on the exporting machine.
+    let fixed: Vec<&str> = settings
+        .rules
+        .iter()
+        .filter(|rule| {
+            rule.actions
+                .iter()
+                .any(|action| matches!(action.target, ActionTarget::Command))
+        })
+        .map(|rule| rule.name.as_str())
+        .collect();
+    if !fixed.is_empty() {
+        preview.warnings.push(format!(
+            "Rules {} use device or app ids from the machine the bundle was exported on, \
+             check their targets after importing",
+            fixed.join(", ")
+        ));
+    }
+
+    Ok(preview)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        profiles::ProfileEntry,
+        rules::{Rule, RuleAction, Trigger},
+        volume::VolumeCommand,
+    };
+
+    fn profile(name: &str, volume: f32) -> Profile {
+        Profile {
+            name: name.into(),
+            devices: vec![ProfileEntry {
+                id: "speakers".into(),
+                name: "Speakers".into(),
+                volume,
+                mute: false,
+            }],
+            apps: vec![],
+        }
+    }
+
+    fn saved(volume: f32) -> SavedVolume {
+        SavedVolume {
+            volume,
+            mute: false,
+        }
+    }
+
+    fn bundle(settings: &Settings) -> Bundle {
+        Bundle {
+            version: BUNDLE_VERSION,
+            created_at: 0,
+            settings: serde_json::to_value(settings).unwrap(),
+            profiles: BTreeMap::new(),
+            app_volumes: HashMap::new(),
+        }
+    }
+
+    fn rule(name: &str, target: ActionTarget) -> Rule {
+        Rule {
+            id: String::new(),
+            name: name.into(),
+            enabled: true,
+            trigger: Trigger::AppCreated,
+            conditions: vec![],
+            actions: vec![RuleAction {
+                target,
+                command: VolumeCommand::ApplicationMute {
+                    request_id: String::new(),
+                    id: 1,
+                },
+            }],
+        }
+    }
+
+    /// Writes `bundle` to a temp file and reads it back.
+    fn read(name: &str, bundle: &Bundle) -> Result<(Settings, Bundle), String> {
+        let path = std::env::temp_dir().join(format!(
+            "volumize-bundle-{}-{}.json",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, serde_json::to_vec(bundle).unwrap()).unwrap();
+        let result = read_bundle(&path);
+        let _ = fs::remove_file(&path);
+        result
+    }
+
+    #[test]
+    fn lists_changed_settings_only() {
+        let current = Settings::default();
+        let mut settings = current.clone();
+        settings.server_name = "Living room".into();
+        settings.server_id = "another machine".into();
+
+        let preview = diff(
+            &current,
+            &BTreeMap::new(),
+            &HashMap::new(),
+            &settings,
+            &bundle(&settings),
+        )
+        .unwrap();
+        assert_eq!(preview.settings_changed, vec!["server_name".to_string()]);
+        assert!(preview.warnings.is_empty());
+    }
+
+    #[test]
+    fn counts_added_and_replaced_entries() {
+        let settings = Settings::default();
+        let profiles = BTreeMap::from([("Night".to_string(), profile("Night", 0.2))]);
+        let app_volumes = HashMap::from([
+            ("same.exe".to_string(), saved(0.5)),
+            ("changed.exe".to_string(), saved(0.5)),
+        ]);
+
+        let mut bundle = bundle(&settings);
+        bundle.profiles = BTreeMap::from([
+            ("Night".to_string(), profile("Night", 0.1)),
+            ("Work".to_string(), profile("Work", 0.6)),
+        ]);
+        bundle.app_volumes = HashMap::from([
+            ("same.exe".to_string(), saved(0.5)),
+            ("changed.exe".to_string(), saved(0.8)),
+            ("new.exe".to_string(), saved(0.3)),
+        ]);
+
+        let preview = diff(&settings, &profiles, &app_volumes, &settings, &bundle).unwrap();
+        assert_eq!(preview.profiles_added, vec!["Work".to_string()]);
+        assert_eq!(preview.profiles_replaced, vec!["Night".to_string()]);
+        assert_eq!(preview.app_volumes_added, 1);
+        assert_eq!(preview.app_volumes_replaced, 1);
+    }
+
+    #[test]
+    fn warns_about_rules_with_fixed_ids() {
+        let current = Settings::default();
+        let mut settings = current.clone();
+        settings.rules = vec![
+            rule("Mute on start", ActionTarget::Command),
+            rule("Mute new apps", ActionTarget::Trigger),
+        ];
+
+        let preview = diff(
+            &current,
+            &BTreeMap::new(),
+            &HashMap::new(),
+            &settings,
+            &bundle(&settings),
+        )
+        .unwrap();
+        assert_eq!(preview.warnings.len(), 1);
+        assert!(preview.warnings[0].contains("Mute on start"));
+        assert!(!preview.warnings[0].contains("Mute new apps"));
+    }
+
+    #[test]
+    fn reads_a_valid_bundle() {
+        let mut bundle = bundle(&Settings::default());
+        bundle.profiles = BTreeMap::from([("Night".to_string(), profile("Night", 0.2))]);
+        bundle.app_volumes = HashMap::from([("app.exe".to_string(), saved(1.0))]);
+
+        let (_, read) = read("valid", &bundle).unwrap();
+        assert_eq!(read.profiles.len(), 1);
+        assert_eq!(read.app_volumes.len(), 1);
+    }
+
+    #[test]
+    fn rejects_bundles_that_dont_validate() {
+        let settings = Settings::default();
+
+        let mut newer = bundle(&settings);
+        newer.version = BUNDLE_VERSION + 1;
+        assert!(read("newer", &newer).is_err());
+
+        let mut renamed = bundle(&settings);
+        renamed.profiles = BTreeMap::from([("Night".to_string(), profile("Day", 0.2))]);
+        assert!(read("renamed", &renamed).is_err());
+
+        let mut unnamed = bundle(&settings);
+        unnamed.profiles = BTreeMap::from([(String::new(), profile("", 0.2))]);
+        assert!(read("unnamed", &unnamed).is_err());
+
+        let mut loud = bundle(&settings);
+        loud.profiles = BTreeMap::from([("Night".to_string(), profile("Night", 1.5))]);
+        assert!(read("loud", &loud).is_err());
+
+        let mut negative = bundle(&settings);
+        negative.app_volumes = HashMap::from([("app.exe".to_string(), saved(-0.1))]);
+        assert!(read("negative", &negative).is_err());
+
+        let mut pathless = bundle(&settings);
+        pathless.app_volumes = HashMap::from([(String::new(), saved(0.5))]);
+        assert!(read("pathless", &pathless).is_err());
+    }
+}
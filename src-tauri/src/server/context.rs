@@ -0,0 +1,90 @@
+use std::sync::{Arc, Mutex};
+
+use crate::types::{
+    app_volumes::AppVolumes,
+    profiles::Profiles,
+    shared::UpdateChange,
+    storage::{Settings, Storage},
+    volume::VolumeCommandSender,
+};
+
+use super::{
+    runtime as rt, ClientInfo, DiscoveryStatus, IpcServerState, ServiceDiscovery,
+    WebSocketServerState,
+};
+
+/// Events the server reports to whoever is hosting it.
+/// - The desktop app forwards them to the webview and tray, the daemon has no UI to tell.
+pub trait ServerEvents: Send + Sync {
+    fn update(&self, _change: &UpdateChange) {}
+    fn clients_changed(&self, _clients: &[ClientInfo]) {}
+    fn client_connected(&self, _client: &ClientInfo) {}
+    /// Carries the counters as they were when the connection ended.
+    fn client_disconnected(&self, _client: &ClientInfo) {}
+    /// Also sent when an `OnDuration` window closes by itself.
+    fn discovery_changed(&self, _status: &DiscoveryStatus) {}
+    fn settings_changed(&self, _settings: &Settings) {}
+    /// Names of every saved profile.
+    fn profiles_changed(&self, _profiles: &[String]) {}
+}
+
+/// Drops every event.
+pub struct NoEvents;
+
+impl ServerEvents for NoEvents {}
+
+/// State shared by the server modules, cheap to clone.
+/// - Every part shares its inner state between clones, the desktop app also
+///   manages each of them as Tauri state for its commands.
+#[derive(Clone)]
+pub struct ServerContext {
+    pub volume: VolumeCommandSender,
+    pub websocket: WebSocketServerState,
+    pub discovery: ServiceDiscovery,
+    pub ipc: IpcServerState,
+    pub storage: Storage,
+    pub app_volumes: AppVolumes,
+    pub profiles: Profiles,
+    pub events: Arc<dyn ServerEvents>,
+    /// Held while settings are applied, the settings watcher and clients take turns.
+    pub settings_lock: Arc<Mutex<()>>,
+}
+
+impl ServerContext {
+    pub fn new(storage: Storage, events: Arc<dyn ServerEvents>) -> Self {
+        Self {
+            volume: VolumeCommandSender::new(),
+            websocket: Default::default(),
+            discovery: Default::default(),
+            ipc: Default::default(),
+            app_volumes: AppVolumes::new(storage.config_dir()),
+            profiles: Profiles::new(storage.config_dir()),
+            storage,
+            events,
+            settings_lock: Default::default(),
+        }
+    }
+
+    /// Stops the volume thread and every server, blocking until they are done.
+    /// - Not to be called from the async runtime.
+    pub fn shutdown(&self) {
+        if let Err(e) = self.volume.shutdown() {
+            eprintln!("Volume thread shutdown error: {}", e);
+        }
+        if let Err(e) = self.app_volumes.save() {
+            eprintln!("Failed to save app volumes: {}", e);
+        }
+
+        rt::block_on(async {
+            if let Err(e) = self.discovery.shutdown().await {
+                eprintln!("Service thread shutdown error: {}", e);
+            }
+            if let Err(e) = self.websocket.shutdown().await {
+                eprintln!("WebSocket server shutdown error: {}", e);
+            }
+            if let Err(e) = self.ipc.shutdown().await {
+                eprintln!("IPC server shutdown error: {}", e);
+            }
+        });
+    }
+}
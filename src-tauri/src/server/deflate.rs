@@ -0,0 +1,548 @@
+use std::{
+    io::{Cursor, Error, ErrorKind, Result as IoResult},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use flate2::{Compress, Decompress, FlushCompress, FlushDecompress, Status};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::tungstenite::{
+    handshake::server::{ErrorResponse, Request, Response},
+    http::{header::SEC_WEBSOCKET_EXTENSIONS, HeaderValue},
+    protocol::frame::{
+        coding::{Data, OpCode},
+        Frame, FrameHeader,
+    },
+    Message,
+};
+
+use crate::types::storage::Compression;
+
+use super::http::MAX_HEAD_SIZE;
+
+const EXTENSION_NAME: &str = "permessage-deflate";
+/// Every message is compressed on its own, neither side keeps a window between messages.
+const EXTENSION_RESPONSE: &str =
+    "permessage-deflate; server_no_context_takeover; client_no_context_takeover";
+/// Trailer of a sync flush, stripped by the sender and added back by the receiver.
+const DEFLATE_TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+/// Same limits as tungstenite applies to uncompressed frames and messages.
+const MAX_FRAME_SIZE: u64 = 16 << 20;
+const MAX_MESSAGE_SIZE: usize = 64 << 20;
+const READ_CHUNK: usize = 8 * 1024;
+
+// ============================ Handshake ============================
+/// Handshake callback accepting permessage-deflate when enabled and offered.
+/// - `negotiated` is set when the extension was accepted.
+/// - The error type is tungstenite's, it can't be made smaller here.
+#[allow(clippy::result_large_err)]
+pub fn negotiate<'a>(
+    compression: &'a Compression,
+    negotiated: &'a mut bool,
+) -> impl FnOnce(&Request, Response) -> Result<Response, ErrorResponse> + Unpin + 'a {
+    move |request: &Request, mut response: Response| {
+        *negotiated = compression.enabled
+            && request
+                .headers()
+                .get_all(SEC_WEBSOCKET_EXTENSIONS)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .any(accepts_offer);
+
+        if *negotiated {
+            response.headers_mut().insert(
+                SEC_WEBSOCKET_EXTENSIONS,
+                HeaderValue::from_static(EXTENSION_RESPONSE),
+            );
+        }
+        Ok(response)
+    }
+}
+
+/// `server_max_window_bits` below 15 is declined, the compressor always uses a full window.
+fn accepts_offer(offer: &str) -> bool {
+    let mut params = offer.split(';').map(|param| param.trim());
+    if params.next() != Some(EXTENSION_NAME) {
+        return false;
+    }
+
+    params.all(|param| match param.split_once('=') {
+        Some(("server_max_window_bits", bits)) => bits.trim_matches('"') == "15",
+        Some(("client_max_window_bits", _)) => true,
+        Some(_) => false,
+        None => matches!(
+            param,
+            "server_no_context_takeover" | "client_no_context_takeover" | "client_max_window_bits"
+        ),
+    })
+}
+
+// ============================ Outgoing ============================
+/// Compresses Text and Binary messages of at least `threshold` bytes into a single frame.
+/// - Returns the message unchanged when compressing doesn't make it smaller.
+pub fn compress(message: Message, threshold: usize) -> Message {
+    let (opcode, payload) = match &message {
+        Message::Text(text) if text.len() >= threshold => (Data::Text, text.as_bytes()),
+        Message::Binary(data) if data.len() >= threshold => (Data::Binary, &data[..]),
+        _ => return message,
+    };
+
+    let compressed = match deflate(payload) {
+        Some(compressed) if compressed.len() < payload.len() => compressed,
+        _ => return message,
+    };
+
+    let mut frame = Frame::message(compressed, OpCode::Data(opcode), true);
+    frame.header_mut().rsv1 = true;
+    Message::Frame(frame)
+}
+
+fn deflate(data: &[u8]) -> Option<Vec<u8>> {
+    let mut compress = Compress::new(flate2::Compression::fast(), false);
+    let mut output = Vec::with_capacity(data.len() / 2 + 64);
+
+    loop {
+        let consumed = compress.total_in() as usize;
+        compress
+            .compress_vec(&data[consumed..], &mut output, FlushCompress::Sync)
+            .ok()?;
+
+        // The flush is complete once it stops filling the whole buffer.
+        if compress.total_in() as usize == data.len() && output.len() < output.capacity() {
+            break;
+        }
+        output.reserve(output.capacity());
+    }
+
+    if output.ends_with(&DEFLATE_TAIL) {
+        output.truncate(output.len() - DEFLATE_TAIL.len());
+    }
+    Some(output)
+}
+
+fn inflate(data: &[u8]) -> IoResult<Vec<u8>> {
+    let mut decompress = Decompress::new(false);
+    let mut output = Vec::with_capacity(data.len() * 4);
+
+    loop {
+        if output.len() == output.capacity() {
+            if output.len() >= MAX_MESSAGE_SIZE {
+                return Err(invalid_data("Decompressed message too large"));
+            }
+            output.reserve(output.capacity());
+        }
+
+        let consumed = decompress.total_in() as usize;
+        let status = decompress
+            .decompress_vec(&data[consumed..], &mut output, FlushDecompress::Sync)
+            .map_err(invalid_data)?;
+
+        let done = decompress.total_in() as usize == data.len() && output.len() < output.capacity();
+        if done || status == Status::StreamEnd {
+            return Ok(output);
+        }
+    }
+}
+
+fn invalid_data(error: impl ToString) -> Error {
+    Error::new(ErrorKind::InvalidData, error.to_string())
+}
+
+// ============================ Incoming ============================
+/// Stream underneath the WebSocket that inflates compressed client frames.
+/// - tungstenite rejects frames with `rsv1` set, so they are rewritten into
+///   plain frames before it reads them.
+/// - Hands out the handshake request on its own, bytes after it are held back
+///   until the handshake is done and `enable_inflate` had its chance to be called.
+pub struct DeflateStream<S> {
+    inner: S,
+    /// Set once the handshake request has been handed out.
+    handshake_done: bool,
+    inflate: bool,
+    /// Bytes read from `inner` that don't form a complete frame yet.
+    incoming: Vec<u8>,
+    /// Frames ready to be handed to the WebSocket reader.
+    ready: Vec<u8>,
+    ready_pos: usize,
+    /// Opcode and payload of a compressed message still missing fragments.
+    message: Option<(OpCode, Vec<u8>)>,
+}
+
+impl<S> DeflateStream<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            handshake_done: false,
+            inflate: false,
+            incoming: vec![],
+            ready: vec![],
+            ready_pos: 0,
+            message: None,
+        }
+    }
+
+    /// Called after the handshake, before the first frame is read.
+    pub fn enable_inflate(&mut self) {
+        self.inflate = true;
+    }
+
+    /// Moves the handshake request from `incoming` to `ready` once all of it has arrived.
+    fn split_head(&mut self) -> IoResult<()> {
+        let end = self
+            .incoming
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n");
+        match end {
+            Some(end) => {
+                self.ready.extend(self.incoming.drain(..end + 4));
+                self.handshake_done = true;
+                Ok(())
+            }
+            None if self.incoming.len() > MAX_HEAD_SIZE => {
+                Err(invalid_data("Handshake request too large"))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Moves every complete frame from `incoming` to `ready`.
+    fn process_frames(&mut self) -> IoResult<()> {
+        let mut consumed = 0;
+
+        loop {
+            let mut cursor = Cursor::new(&self.incoming[consumed..]);
+            let (header, length) = match FrameHeader::parse(&mut cursor).map_err(invalid_data)? {
+                Some(parsed) => parsed,
+                None => break,
+            };
+            if length > MAX_FRAME_SIZE {
+                return Err(invalid_data("Frame too large"));
+            }
+
+            let start = consumed + cursor.position() as usize;
+            let end = start + length as usize;
+            if end > self.incoming.len() {
+                break;
+            }
+
+            let compressed = match header.opcode {
+                OpCode::Control(_) => false,
+                OpCode::Data(Data::Continue) => self.message.is_some(),
+                OpCode::Data(_) => header.rsv1,
+            };
+
+            match compressed {
+                true => {
+                    let payload = self.incoming[start..end].to_vec();
+                    self.inflate_frame(header, payload)?;
+                }
+                false => self.ready.extend_from_slice(&self.incoming[consumed..end]),
+            }
+            consumed = end;
+        }
+
+        self.incoming.drain(..consumed);
+        Ok(())
+    }
+
+    /// Collects the fragments of a compressed message and writes it out as one plain frame.
+    fn inflate_frame(&mut self, header: FrameHeader, mut payload: Vec<u8>) -> IoResult<()> {
+        if let Some(mask) = header.mask {
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+        }
+
+        let (_, compressed) = self.message.get_or_insert((header.opcode, vec![]));
+        compressed.extend_from_slice(&payload);
+        if compressed.len() > MAX_MESSAGE_SIZE {
+            return Err(invalid_data("Compressed message too large"));
+        }
+
+        if !header.is_final {
+            return Ok(());
+        }
+
+        let (opcode, mut compressed) = match self.message.take() {
+            Some(message) => message,
+            None => return Ok(()),
+        };
+        compressed.extend_from_slice(&DEFLATE_TAIL);
+        let data = inflate(&compressed)?;
+
+        // Client frames have to be masked, a zero mask leaves the payload as is.
+        let plain = FrameHeader {
+            is_final: true,
+            rsv1: false,
+            rsv2: header.rsv2,
+            rsv3: header.rsv3,
+            opcode,
+            mask: Some([0; 4]),
+        };
+        plain
+            .format(data.len() as u64, &mut self.ready)
+            .map_err(invalid_data)?;
+        self.ready.extend_from_slice(&data);
+        Ok(())
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for DeflateStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<IoResult<()>> {
+        let this = self.get_mut();
+
+        loop {
+            if !this.handshake_done {
+                this.split_head()?;
+            } else if this.inflate {
+                this.process_frames()?;
+            } else {
+                // Whatever came in with the handshake request, then straight from `inner`.
+                this.ready.append(&mut this.incoming);
+            }
+
+            if this.ready_pos < this.ready.len() {
+                let len = buf.remaining().min(this.ready.len() - this.ready_pos);
+                buf.put_slice(&this.ready[this.ready_pos..this.ready_pos + len]);
+                this.ready_pos += len;
+
+                if this.ready_pos == this.ready.len() {
+                    this.ready.clear();
+                    this.ready_pos = 0;
+                }
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.handshake_done && !this.inflate {
+                return Pin::new(&mut this.inner).poll_read(cx, buf);
+            }
+
+            let mut chunk = [0u8; READ_CHUNK];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf))?;
+
+            // End of stream, an incomplete frame left behind is dropped.
+            if chunk_buf.filled().is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+
+            this.incoming.extend_from_slice(chunk_buf.filled());
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for DeflateStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<IoResult<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+    use tokio::io::{duplex, AsyncWriteExt, DuplexStream};
+    use tokio_tungstenite::{
+        accept_hdr_async,
+        tungstenite::{
+            error::ProtocolError, protocol::frame::coding::Control, protocol::Role,
+            Error as WsError,
+        },
+        WebSocketStream,
+    };
+
+    use super::*;
+
+    const TEXT: &str = "volume volume volume volume volume volume volume volume";
+
+    /// A masked client frame.
+    fn frame(opcode: OpCode, is_final: bool, rsv1: bool, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let header = FrameHeader {
+            is_final,
+            rsv1,
+            rsv2: false,
+            rsv3: false,
+            opcode,
+            mask: Some(mask),
+        };
+
+        let mut bytes = vec![];
+        header.format(payload.len() as u64, &mut bytes).unwrap();
+        bytes.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        bytes
+    }
+
+    /// Every message the server reads from `bytes`, stops at the end of the stream.
+    async fn receive(
+        mut stream: WebSocketStream<DeflateStream<DuplexStream>>,
+    ) -> Result<Vec<Message>, WsError> {
+        let mut messages = vec![];
+        while let Some(message) = stream.next().await {
+            match message {
+                Ok(message) => messages.push(message),
+                Err(WsError::Protocol(ProtocolError::ResetWithoutClosingHandshake)) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(messages)
+    }
+
+    async fn receive_frames(bytes: Vec<u8>, inflate: bool) -> Result<Vec<Message>, WsError> {
+        let (mut client, server) = duplex(1 << 16);
+        client.write_all(&bytes).await.unwrap();
+        // Still open for writing, pings get an answer.
+        client.shutdown().await.unwrap();
+
+        let mut stream = DeflateStream::new(server);
+        stream.handshake_done = true;
+        stream.inflate = inflate;
+        receive(WebSocketStream::from_raw_socket(stream, Role::Server, None).await).await
+    }
+
+    #[test]
+    fn round_trips() {
+        let compressed = deflate(TEXT.as_bytes()).unwrap();
+        assert!(!compressed.ends_with(&DEFLATE_TAIL));
+
+        let mut data = compressed.clone();
+        data.extend_from_slice(&DEFLATE_TAIL);
+        assert_eq!(inflate(&data).unwrap(), TEXT.as_bytes());
+
+        let empty = deflate(&[]).unwrap();
+        assert!(inflate(&[empty, DEFLATE_TAIL.to_vec()].concat())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn compresses_only_when_worth_it() {
+        match compress(Message::text(TEXT), 16) {
+            Message::Frame(frame) => {
+                assert!(frame.header().rsv1);
+                assert!(frame.payload().len() < TEXT.len());
+            }
+            other => panic!("Not compressed: {:?}", other),
+        }
+
+        assert_eq!(compress(Message::text(TEXT), 1024), Message::text(TEXT));
+        let noise: Vec<u8> = (0..64u32).map(|i| (i * 7919 % 251) as u8).collect();
+        assert_eq!(
+            compress(Message::binary(noise.clone()), 16),
+            Message::binary(noise)
+        );
+        assert_eq!(
+            compress(Message::Ping(vec![1; 64].into()), 0),
+            Message::Ping(vec![1; 64].into())
+        );
+    }
+
+    #[test]
+    fn parses_offers() {
+        assert!(accepts_offer("permessage-deflate"));
+        assert!(accepts_offer(" permessage-deflate; client_max_window_bits"));
+        assert!(accepts_offer(
+            "permessage-deflate; server_no_context_takeover; client_no_context_takeover"
+        ));
+        assert!(accepts_offer(
+            "permessage-deflate; client_max_window_bits=10"
+        ));
+        assert!(accepts_offer(
+            "permessage-deflate; server_max_window_bits=\"15\""
+        ));
+
+        assert!(!accepts_offer("x-webkit-deflate-frame"));
+        assert!(!accepts_offer(
+            "permessage-deflate; server_max_window_bits=10"
+        ));
+        assert!(!accepts_offer("permessage-deflate; unknown_param"));
+        assert!(!accepts_offer(
+            "permessage-deflate; server_no_context_takeover=1"
+        ));
+    }
+
+    #[tokio::test]
+    async fn inflates_fragmented_messages_around_control_frames() {
+        let compressed = deflate(TEXT.as_bytes()).unwrap();
+        let (first, second) = compressed.split_at(compressed.len() / 2);
+
+        let bytes = [
+            frame(OpCode::Data(Data::Text), false, true, first),
+            frame(OpCode::Control(Control::Ping), true, false, b"ping"),
+            frame(OpCode::Data(Data::Continue), true, false, second),
+            frame(OpCode::Data(Data::Text), true, false, b"plain"),
+        ]
+        .concat();
+
+        let messages = receive_frames(bytes, true).await.unwrap();
+        assert_eq!(
+            messages,
+            [
+                Message::Ping(b"ping".to_vec().into()),
+                Message::text(TEXT),
+                Message::text("plain"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn leaves_rsv1_alone_until_enabled() {
+        let compressed = deflate(TEXT.as_bytes()).unwrap();
+        let bytes = frame(OpCode::Data(Data::Text), true, true, &compressed);
+        assert!(receive_frames(bytes, false).await.is_err());
+
+        let bytes = frame(OpCode::Data(Data::Binary), true, false, TEXT.as_bytes());
+        let messages = receive_frames(bytes, false).await.unwrap();
+        assert_eq!(messages, [Message::binary(TEXT.as_bytes().to_vec())]);
+    }
+
+    #[tokio::test]
+    async fn rejects_corrupt_payloads() {
+        let bytes = frame(OpCode::Data(Data::Text), true, true, &[0xff; 16]);
+        assert!(receive_frames(bytes, true).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn inflates_frames_sent_with_the_handshake() {
+        let request = "GET / HTTP/1.1\r\n\
+                       Host: localhost\r\n\
+                       Upgrade: websocket\r\n\
+                       Connection: Upgrade\r\n\
+                       Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                       Sec-WebSocket-Version: 13\r\n\
+                       Sec-WebSocket-Extensions: permessage-deflate\r\n\r\n";
+        let compressed = deflate(TEXT.as_bytes()).unwrap();
+        let bytes = [
+            request.as_bytes().to_vec(),
+            frame(OpCode::Data(Data::Text), true, true, &compressed),
+        ]
+        .concat();
+
+        let (mut client, server) = duplex(1 << 16);
+        client.write_all(&bytes).await.unwrap();
+
+        let compression = Compression::default();
+        let mut negotiated = false;
+        let callback = negotiate(&compression, &mut negotiated);
+        let mut stream = accept_hdr_async(DeflateStream::new(server), callback)
+            .await
+            .unwrap();
+        assert!(negotiated);
+        stream.get_mut().enable_inflate();
+        client.shutdown().await.unwrap();
+
+        assert_eq!(receive(stream).await.unwrap(), [Message::text(TEXT)]);
+    }
+}
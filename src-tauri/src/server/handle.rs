@@ -1,23 +1,56 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, time::Duration};
 
 use futures_util::{stream::SplitSink, SinkExt, StreamExt};
-use tauri::async_runtime as rt;
-use tauri::AppHandle;
-use tokio::{net::TcpStream, sync::mpsc};
-use tokio_tungstenite::{accept_async, tungstenite::Message, WebSocketStream};
+use tokio::{
+    net::TcpStream,
+    time::{interval, sleep},
+};
+use tokio_tungstenite::{accept_hdr_async, tungstenite::Message, WebSocketStream};
 use uuid::Uuid;
 
-use super::{incoming::handle_incoming_messages, ClientInfo, ClientMap};
+use crate::types::storage::{Compression, Heartbeat};
+
+use super::{
+    deflate::{self, DeflateStream},
+    http,
+    incoming::handle_incoming_messages,
+    list_clients,
+    queue::{client_queue, ClientReceiver},
+    rest, runtime as rt, unix_millis, ClientInfo, ClientMap, ServerContext,
+};
+
+pub type ClientStream = WebSocketStream<DeflateStream<TcpStream>>;
+
+/// Clients that don't say Hello by then are announced by their address.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(3);
 
 pub async fn handle_client(
     stream: TcpStream,
     peer_addr: SocketAddr,
-    clients: ClientMap,
-    app_handle: AppHandle,
+    heartbeat: Heartbeat,
+    compression: Compression,
+    context: ServerContext,
 ) {
+    let head = match http::peek_request_head(&stream).await {
+        Ok(head) => head,
+        Err(e) => {
+            eprintln!("Failed to read request from {}: {}", peer_addr, e);
+            return;
+        }
+    };
+
+    // Plain HTTP requests are served by the REST API on the same port.
+    if !http::is_websocket_upgrade(&head) {
+        return rest::handle_rest_request(stream, head, context).await;
+    }
+
+    let clients = context.websocket.clients.clone();
+
     let client_id = Uuid::new_v4().to_string();
 
-    let ws_stream = match accept_async(stream).await {
+    let mut negotiated = false;
+    let callback = deflate::negotiate(&compression, &mut negotiated);
+    let mut ws_stream = match accept_hdr_async(DeflateStream::new(stream), callback).await {
         Ok(ws) => ws,
         Err(e) => {
             eprintln!("Failed to accept WebSocket connection: {}", e);
@@ -25,50 +58,148 @@ pub async fn handle_client(
         }
     };
 
+    let threshold = match negotiated {
+        true => {
+            ws_stream.get_mut().enable_inflate();
+            Some(compression.threshold)
+        }
+        false => None,
+    };
+
     println!("Client {} connected from {}", client_id, peer_addr);
 
     let (write, read) = ws_stream.split();
-    let (tx, rx) = mpsc::unbounded_channel();
+    let (tx, rx) = client_queue();
 
     // Add client to the map
-    clients.lock().await.insert(
+    let info = ClientInfo::new(client_id.clone(), peer_addr);
+    clients
+        .lock()
+        .await
+        .insert(client_id.clone(), (info.clone(), tx));
+    emit_clients_changed(&context).await;
+
+    let mut write_task = rt::spawn(handle_outgoing_messages(
+        write,
+        rx,
+        threshold,
         client_id.clone(),
-        (
-            ClientInfo {
-                id: client_id.clone(),
-                address: peer_addr.to_string(),
-            },
-            tx,
-        ),
-    );
-
-    let mut write_task = rt::spawn(handle_outgoing_messages(write, rx, client_id.clone()));
+        clients.clone(),
+    ));
     let mut read_task = rt::spawn(handle_incoming_messages(
         read,
         client_id.clone(),
+        peer_addr,
+        context.clone(),
+    ));
+    let mut heartbeat_task = rt::spawn(handle_heartbeat(
+        heartbeat,
+        client_id.clone(),
         clients.clone(),
-        app_handle,
     ));
+    let announce_task = rt::spawn({
+        let context = context.clone();
+        let client_id = client_id.clone();
+        async move {
+            sleep(HELLO_TIMEOUT).await;
+            announce_client(&context, &client_id).await;
+        }
+    });
 
-    let _ = tokio::select! {
-        _ = &mut write_task => { read_task.abort();  read_task.await },
-        _ = &mut read_task  => { write_task.abort(); write_task.await },
+    tokio::select! {
+        _ = &mut write_task     => {},
+        _ = &mut read_task      => {},
+        _ = &mut heartbeat_task => {},
     };
+    write_task.abort();
+    read_task.abort();
+    heartbeat_task.abort();
+    announce_task.abort();
 
-    // Cleanup: remove client from map
-    clients.lock().await.remove(&client_id);
+    // Cleanup: remove client from map, already gone when disconnected from the tray.
+    let info = match clients.lock().await.remove(&client_id) {
+        Some((info, _)) => info,
+        None => info,
+    };
+    if info.is_announced() {
+        context.events.client_disconnected(&info);
+    }
+    emit_clients_changed(&context).await;
     println!("Client {} disconnected", client_id);
 }
 
+/// Sends `client_connected` once, on the client's Hello or after `HELLO_TIMEOUT`.
+pub async fn announce_client(context: &ServerContext, client_id: &str) {
+    let info = match context.websocket.clients.lock().await.get(client_id) {
+        Some((info, _)) => info.clone(),
+        None => return,
+    };
+
+    if info.mark_announced() {
+        context.events.client_connected(&info);
+    }
+}
+
+/// Writes queued messages to the client.
+/// - `threshold` is set when permessage-deflate was negotiated.
 pub async fn handle_outgoing_messages(
-    mut write: SplitSink<WebSocketStream<TcpStream>, Message>,
-    mut rx: mpsc::UnboundedReceiver<Message>,
+    mut write: SplitSink<ClientStream, Message>,
+    mut rx: ClientReceiver,
+    threshold: Option<usize>,
     client_id: String,
+    clients: ClientMap,
 ) {
     while let Some(message) = rx.recv().await {
+        let message = match threshold {
+            Some(threshold) => deflate::compress(message, threshold),
+            None => message,
+        };
+
         if let Err(e) = write.send(message).await {
             eprintln!("Error sending message to client {}: {}", client_id, e);
             break;
         }
+
+        if let Some((info, _)) = clients.lock().await.get_mut(&client_id) {
+            info.messages_sent += 1;
+        }
+    }
+}
+
+/// Pings the client every `interval` and returns once it stops responding.
+/// - Ping payload carries the send time, the pong echoes it back for latency.
+async fn handle_heartbeat(heartbeat: Heartbeat, client_id: String, clients: ClientMap) {
+    if heartbeat.interval.is_zero() {
+        return std::future::pending().await;
     }
+
+    let max_silence = (heartbeat.interval + heartbeat.timeout).as_millis() as u64;
+    let mut ticker = interval(heartbeat.interval);
+    ticker.tick().await; // Skip the first immediate tick.
+
+    loop {
+        ticker.tick().await;
+
+        let client_lock = clients.lock().await;
+        let (info, sender) = match client_lock.get(&client_id) {
+            Some(client) => client,
+            None => break,
+        };
+
+        let now = unix_millis();
+        if now.saturating_sub(info.last_seen) > max_silence {
+            println!("Client {} stopped responding", client_id);
+            break;
+        }
+
+        let payload = now.to_be_bytes().to_vec();
+        if sender.send(Message::Ping(payload.into())).is_err() {
+            break;
+        }
+    }
+}
+
+pub async fn emit_clients_changed(context: &ServerContext) {
+    let list = list_clients(&context.websocket.clients).await;
+    context.events.clients_changed(&list);
 }
@@ -0,0 +1,346 @@
+use std::{
+    io::{Error, ErrorKind, Result as IoResult},
+    time::Duration,
+};
+
+use serde::Serialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+pub const MAX_HEAD_SIZE: usize = 8 * 1024;
+const MAX_BODY_SIZE: usize = 64 * 1024;
+const MAX_HEADERS: usize = 64;
+const HEAD_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every value of `key` in the query string, percent decoded.
+    pub fn query_values(&self, key: &str) -> Vec<String> {
+        let query = match &self.query {
+            Some(query) => query,
+            None => return vec![],
+        };
+
+        query
+            .split('&')
+            .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+            .filter(|(name, _)| *name == key)
+            .map(|(_, value)| percent_decode(&value.replace('+', " ")))
+            .collect()
+    }
+
+    /// Path split on `/`, each segment percent decoded.
+    pub fn segments(&self) -> Vec<String> {
+        self.path
+            .trim_matches('/')
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(percent_decode)
+            .collect()
+    }
+}
+
+/// Reads the request head without consuming it.
+/// - The WebSocket handshake needs to read the same bytes again.
+/// - Peers that don't send a full head within `HEAD_TIMEOUT` get `TimedOut`.
+pub async fn peek_request_head(stream: &TcpStream) -> IoResult<Vec<u8>> {
+    match tokio::time::timeout(HEAD_TIMEOUT, peek_head(stream)).await {
+        Ok(result) => result,
+        Err(_) => Err(ErrorKind::TimedOut.into()),
+    }
+}
+
+async fn peek_head(stream: &TcpStream) -> IoResult<Vec<u8>> {
+    let mut buf = vec![0u8; MAX_HEAD_SIZE];
+
+    loop {
+        let len = stream.peek(&mut buf).await?;
+        if len == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+
+        if let Some(end) = find_head_end(&buf[..len]) {
+            buf.truncate(end);
+            return Ok(buf);
+        }
+
+        if len == buf.len() {
+            return Err(Error::new(ErrorKind::InvalidData, "Request head too large"));
+        }
+
+        // Peeking returns the same bytes until more arrive.
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+fn find_head_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|pos| pos + 4)
+}
+
+pub fn is_websocket_upgrade(head: &[u8]) -> bool {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut request = httparse::Request::new(&mut headers);
+
+    if request.parse(head).is_err() {
+        return false;
+    }
+
+    request.headers.iter().any(|header| {
+        header.name.eq_ignore_ascii_case("upgrade")
+            && String::from_utf8_lossy(header.value).eq_ignore_ascii_case("websocket")
+    })
+}
+
+/// Consumes the peeked `head` and the body from the stream.
+pub async fn read_request(stream: &mut TcpStream, head: &[u8]) -> IoResult<HttpRequest> {
+    let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut parsed = httparse::Request::new(&mut headers);
+    match parsed.parse(head) {
+        Ok(httparse::Status::Complete(_)) => {}
+        _ => return Err(invalid("Malformed request head")),
+    }
+
+    let target = parsed.path.unwrap_or("/");
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), Some(query.to_string())),
+        None => (target.to_string(), None),
+    };
+
+    let mut request = HttpRequest {
+        method: parsed.method.unwrap_or("GET").to_uppercase(),
+        path,
+        query,
+        headers: parsed
+            .headers
+            .iter()
+            .map(|h| {
+                let value = String::from_utf8_lossy(h.value).into_owned();
+                (h.name.to_string(), value)
+            })
+            .collect(),
+        body: vec![],
+    };
+
+    let mut consumed = vec![0u8; head.len()];
+    stream.read_exact(&mut consumed).await?;
+
+    let content_length = match request.header("content-length") {
+        Some(value) => value
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| invalid("Invalid Content-Length"))?,
+        None => 0,
+    };
+
+    if content_length > MAX_BODY_SIZE {
+        return Err(invalid("Request body too large"));
+    }
+
+    request.body = vec![0u8; content_length];
+    stream.read_exact(&mut request.body).await?;
+
+    Ok(request)
+}
+
+pub struct HttpResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn json<T: Serialize>(status: u16, value: &T) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_vec(value).unwrap_or_default(),
+        }
+    }
+
+    pub fn bytes(status: u16, content_type: &'static str, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    /// No CORS headers, web pages on other origins can't read the responses.
+    pub async fn write_to(self, stream: &mut TcpStream) -> IoResult<()> {
+        let head = format!(
+            "HTTP/1.1 {} {}\r\n\
+             Content-Type: {}\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n",
+            self.status,
+            reason_phrase(self.status),
+            self.content_type,
+            self.body.len(),
+        );
+
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(&self.body).await?;
+        stream.shutdown().await
+    }
+}
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        415 => "Unsupported Media Type",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+pub fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| {
+            let hex = std::str::from_utf8(hex).ok()?;
+            u8::from_str_radix(hex, 16).ok()
+        });
+
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn get(path: &str, query: Option<&str>) -> HttpRequest {
+        HttpRequest {
+            method: "GET".into(),
+            path: path.into(),
+            query: query.map(String::from),
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    #[test]
+    fn percent_decodes() {
+        assert_eq!(percent_decode("a%20b"), "a b");
+        assert_eq!(percent_decode("%E2%9C%93"), "\u{2713}");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+    }
+
+    #[test]
+    fn splits_path_segments() {
+        let request = get("/api//devices/%7Ba%2Fb%7D/volume/", None);
+        assert_eq!(request.segments(), ["api", "devices", "{a/b}", "volume"]);
+        assert!(get("/", None).segments().is_empty());
+    }
+
+    #[test]
+    fn collects_query_values() {
+        let request = get(
+            "/api/events",
+            Some("id=app%3A12&last_event_id=1-2&id=device:a+b&id"),
+        );
+        assert_eq!(request.query_values("id"), ["app:12", "device:a b", ""]);
+        assert_eq!(request.query_values("last_event_id"), ["1-2"]);
+        assert!(request.query_values("missing").is_empty());
+        assert!(get("/", None).query_values("id").is_empty());
+    }
+
+    #[test]
+    fn detects_websocket_upgrades() {
+        let upgrade = b"GET / HTTP/1.1\r\nHost: x\r\nUpgrade: WebSocket\r\n\r\n";
+        let plain = b"GET /api/devices HTTP/1.1\r\nHost: x\r\n\r\n";
+        assert!(is_websocket_upgrade(upgrade));
+        assert!(!is_websocket_upgrade(plain));
+        assert!(!is_websocket_upgrade(b"not http"));
+    }
+
+    #[tokio::test]
+    async fn reads_the_request_after_peeking() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        let body = r#"{"volume": 0.5}"#;
+        let raw = format!(
+            "put /api/devices/a%20b/volume?x=1 HTTP/1.1\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        client.write_all(raw.as_bytes()).await.unwrap();
+
+        let head = peek_request_head(&stream).await.unwrap();
+        let request = read_request(&mut stream, &head).await.unwrap();
+
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.segments(), ["api", "devices", "a b", "volume"]);
+        assert_eq!(request.query_values("x"), ["1"]);
+        assert_eq!(request.header("content-type"), Some("application/json"));
+        assert_eq!(request.body, body.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn refuses_bodies_over_the_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        let raw = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_SIZE + 1
+        );
+        client.write_all(raw.as_bytes()).await.unwrap();
+
+        let head = peek_request_head(&stream).await.unwrap();
+        let error = read_request(&mut stream, &head).await.err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
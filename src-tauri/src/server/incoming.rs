@@ -1,41 +1,155 @@
-use std::error::Error;
+use std::{error::Error, net::SocketAddr};
 
 use futures_util::{stream::SplitStream, StreamExt};
+use serde::Deserialize;
 use serde_json::json;
-use tauri::{AppHandle, Manager};
-use tokio::{
-    net::TcpStream,
-    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
-};
-use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio_tungstenite::tungstenite::Message;
 
 use crate::types::shared::VolumeResult;
+use crate::types::storage::Settings;
 use crate::types::volume::{VolumeCommand, VolumeCommandSender};
 
-use super::ClientMap;
+use super::{
+    bind_settings_changed, disconnect_client,
+    handle::{announce_client, emit_clients_changed, ClientStream},
+    list_clients,
+    profiles::apply_profile,
+    settings::{prepare_settings, update_settings},
+    unix_millis, ClientSender, ServerContext, PROTOCOL_VERSION,
+};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ClientCommand {
+    Hello {
+        request_id: String,
+        device_name: Option<String>,
+        protocol_version: Option<u32>,
+    },
+    ListClients {
+        request_id: String,
+    },
+    DisconnectClient {
+        request_id: String,
+        id: String,
+    },
+    GetSettings {
+        request_id: String,
+    },
+    ListProfiles {
+        request_id: String,
+    },
+    /// Answers with a `ProfileReport`.
+    ApplyProfile {
+        request_id: String,
+        name: String,
+    },
+    /// Takes the full settings object, see `update_settings`.
+    UpdateSettings {
+        request_id: String,
+        settings: Box<Settings>,
+    },
+}
+
+impl ClientCommand {
+    /// Commands that act on other clients or the server itself, or read its settings.
+    /// - Only accepted from this machine, anyone on the network can connect.
+    fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            ClientCommand::ListClients { .. }
+                | ClientCommand::DisconnectClient { .. }
+                | ClientCommand::GetSettings { .. }
+                | ClientCommand::UpdateSettings { .. }
+        )
+    }
+
+    /// `type` of the response, the request id or the command name when there is none.
+    fn response_type(&self) -> String {
+        let (request_id, name) = match self {
+            ClientCommand::Hello { request_id, .. } => (request_id, "hello"),
+            ClientCommand::ListClients { request_id } => (request_id, "list_clients"),
+            ClientCommand::DisconnectClient { request_id, .. } => (request_id, "disconnect_client"),
+            ClientCommand::GetSettings { request_id } => (request_id, "get_settings"),
+            ClientCommand::ListProfiles { request_id } => (request_id, "list_profiles"),
+            ClientCommand::ApplyProfile { request_id, .. } => (request_id, "apply_profile"),
+            ClientCommand::UpdateSettings { request_id, .. } => (request_id, "update_settings"),
+        };
+        or_name(request_id.clone(), name)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum IncomingMessage {
+    Volume(VolumeCommand),
+    Client(ClientCommand),
+}
 
 pub async fn handle_incoming_messages(
-    mut read: SplitStream<WebSocketStream<TcpStream>>,
+    mut read: SplitStream<ClientStream>,
     client_id: String,
-    clients: ClientMap,
-    app_handle: AppHandle,
+    peer_addr: SocketAddr,
+    context: ServerContext,
 ) {
+    let clients = &context.websocket.clients;
+
     while let Some(msg) = read.next().await {
+        if msg.is_ok() {
+            if let Some((info, _)) = clients.lock().await.get_mut(&client_id) {
+                info.last_seen = unix_millis();
+            }
+        }
+
         match msg {
-            Ok(Message::Text(text)) => match parse_action(&text) {
-                Ok(command) => {
-                    if let Err(error) =
-                        handle_volume_command(command, &client_id, &clients, &app_handle).await
-                    {
-                        eprintln!("Failed to handle volume command: {}", error)
+            Ok(Message::Text(text)) => {
+                if let Some((info, _)) = clients.lock().await.get_mut(&client_id) {
+                    info.messages_received += 1;
+                }
+
+                let client_sender = match clients.lock().await.get(&client_id) {
+                    Some((_, sender)) => sender.clone(),
+                    None => break,
+                };
+
+                // Failures are answered too, the client would wait for a response otherwise.
+                match parse_action(&text) {
+                    Ok(IncomingMessage::Volume(command)) => {
+                        let response_type = response_type(&command);
+                        if let Err(error) =
+                            handle_volume_command(command, &client_sender, &context.volume).await
+                        {
+                            eprintln!("Failed to handle volume command: {}", error);
+                            send_error(&client_sender, &response_type, &error.to_string());
+                        }
                     }
+                    Ok(IncomingMessage::Client(command)) => {
+                        let response_type = command.response_type();
+                        if let Err(error) =
+                            handle_client_command(command, &client_id, &peer_addr, &context).await
+                        {
+                            eprintln!("Failed to handle client command: {}", error);
+                            send_error(&client_sender, &response_type, &error.to_string());
+                        }
+                    }
+                    Err(err) => eprintln!("Parse error: {}\n - Original: {}", err, text),
                 }
-                Err(err) => eprintln!("Parse error: {}\n - Original: {}", err, text),
-            },
+            }
             Ok(Message::Close(_)) => {
                 println!("Client {} closed connection", client_id);
                 break;
             }
+            Ok(Message::Pong(payload)) => {
+                let sent_at = match <[u8; 8]>::try_from(payload.as_ref()) {
+                    Ok(bytes) => u64::from_be_bytes(bytes),
+                    Err(_) => continue,
+                };
+
+                if let Some((info, _)) = clients.lock().await.get_mut(&client_id) {
+                    info.latency_ms = Some(unix_millis().saturating_sub(sent_at));
+                }
+            }
             Ok(Message::Ping(_)) => {
                 let client_lock = clients.lock().await;
                 if let Some((_, client_sender)) = client_lock.get(&client_id) {
@@ -54,17 +168,13 @@ pub async fn handle_incoming_messages(
     }
 }
 
-async fn handle_volume_command(
+/// Dispatches a `VolumeCommand` and queues its response on `client_sender`.
+/// - Shared by WebSocket clients and the local IPC endpoint.
+pub async fn handle_volume_command(
     command: VolumeCommand,
-    client_id: &str,
-    clients: &ClientMap,
-    app_handle: &AppHandle,
+    client_sender: &ClientSender,
+    state: &VolumeCommandSender,
 ) -> Result<(), Box<dyn Error>> {
-    let client_lock = clients.lock().await;
-    let (_, client_sender) = client_lock.get(client_id).ok_or("Client not found")?;
-
-    let state = app_handle.state::<VolumeCommandSender>();
-
     match command {
         // ==================== DEVICE ====================
         VolumeCommand::DeviceGetVolume { request_id, id, .. } => {
@@ -75,8 +185,8 @@ async fn handle_volume_command(
                     sender: tx,
                     id,
                 },
-                &client_sender,
-                &state,
+                client_sender,
+                state,
                 rx,
             )
             .await
@@ -90,8 +200,8 @@ async fn handle_volume_command(
                     sender: tx,
                     id,
                 },
-                &client_sender,
-                &state,
+                client_sender,
+                state,
                 rx,
             )
             .await
@@ -104,8 +214,8 @@ async fn handle_volume_command(
                     sender: tx,
                     id,
                 },
-                &client_sender,
-                &state,
+                client_sender,
+                state,
                 rx,
             )
             .await
@@ -118,8 +228,8 @@ async fn handle_volume_command(
                     sender: tx,
                     id,
                 },
-                &client_sender,
-                &state,
+                client_sender,
+                state,
                 rx,
             )
             .await
@@ -133,8 +243,8 @@ async fn handle_volume_command(
                     sender: tx,
                     id,
                 },
-                &client_sender,
-                &state,
+                client_sender,
+                state,
                 rx,
             )
             .await
@@ -146,28 +256,125 @@ async fn handle_volume_command(
                     request_id,
                     sender: tx,
                 },
-                &client_sender,
-                &state,
+                client_sender,
+                state,
                 rx,
             )
             .await
         }
         rest => {
-            let request_id = {
-                let id = rest.get_request_id();
-                match id.is_empty() {
-                    true => rest.get_name(),
-                    false => id,
-                }
-            };
+            let request_id = response_type(&rest);
             let tt = create_json_response(&request_id, &"REQUEST ACCEPTED");
             let _ = client_sender.send(tt.into());
 
-            send_command(rest, &state)
+            send_command(rest, state)
         }
     }
 }
 
+async fn handle_client_command(
+    command: ClientCommand,
+    client_id: &str,
+    peer_addr: &SocketAddr,
+    context: &ServerContext,
+) -> Result<(), Box<dyn Error>> {
+    if command.is_admin() && !is_local(peer_addr) {
+        return Err("Only allowed from this machine".into());
+    }
+
+    let clients = &context.websocket.clients;
+    let client_sender = match clients.lock().await.get(client_id) {
+        Some((_, sender)) => sender.clone(),
+        None => return Err("Client not found".into()),
+    };
+
+    let response = match command {
+        ClientCommand::Hello {
+            request_id,
+            device_name,
+            protocol_version,
+        } => {
+            if let Some((info, _)) = clients.lock().await.get_mut(client_id) {
+                info.device_name = device_name;
+                info.protocol_version = protocol_version;
+            }
+            emit_clients_changed(context).await;
+            announce_client(context, client_id).await;
+
+            let settings = context.storage.get();
+            let data = json!({
+                "client_id": client_id,
+                "protocol_version": PROTOCOL_VERSION,
+                "server_id": settings.server_id,
+                "server_name": settings.display_name(),
+            });
+            create_json_response(&or_name(request_id, "hello"), &data)
+        }
+        ClientCommand::ListClients { request_id } => {
+            let list = list_clients(clients).await;
+            create_json_response(&or_name(request_id, "list_clients"), &list)
+        }
+        ClientCommand::DisconnectClient { request_id, id } => {
+            let info = disconnect_client(clients, &id).await?;
+            create_json_response(&or_name(request_id, "disconnect_client"), &info)
+        }
+        ClientCommand::ListProfiles { request_id } => {
+            let names = context.profiles.names();
+            create_json_response(&or_name(request_id, "list_profiles"), &names)
+        }
+        ClientCommand::ApplyProfile { request_id, name } => {
+            let report = apply_profile(context, &name).await?;
+            create_json_response(&or_name(request_id, "apply_profile"), &report)
+        }
+        ClientCommand::GetSettings { request_id } => {
+            create_json_response(&or_name(request_id, "get_settings"), &context.storage.get())
+        }
+        ClientCommand::UpdateSettings {
+            request_id,
+            settings,
+        } => {
+            let name = or_name(request_id, "update_settings");
+            let current = context.storage.get();
+            let settings = prepare_settings(&current, *settings)?;
+
+            // Moving the server closes this connection, the reply is queued before that.
+            // A server that can't be moved answers again with the error.
+            let moves = bind_settings_changed(&current, &settings);
+            if moves {
+                let response = create_json_response(&name, &settings);
+                client_sender
+                    .send(response.into())
+                    .map_err(|e| e.to_string())?;
+            }
+
+            // Restarting the server blocks on its locks, which can't happen on the runtime.
+            let context = context.clone();
+            let settings =
+                tokio::task::spawn_blocking(move || update_settings(&context, settings)).await??;
+            if moves {
+                return Ok(());
+            }
+            create_json_response(&name, &settings)
+        }
+    };
+
+    client_sender
+        .send(response.into())
+        .map_err(|e| e.to_string().into())
+}
+
+/// Loopback peers, IPv4 addresses mapped into IPv6 included.
+fn is_local(peer_addr: &SocketAddr) -> bool {
+    peer_addr.ip().to_canonical().is_loopback()
+}
+
+fn or_name(request_id: String, name: &str) -> String {
+    match request_id.is_empty() {
+        true => name.to_string(),
+        false => request_id,
+    }
+}
+
 fn send_command(
     command: VolumeCommand,
     v_state: &VolumeCommandSender,
@@ -179,17 +386,11 @@ fn send_command(
 
 async fn handle_command_with_response<T: serde::Serialize>(
     command: VolumeCommand,
-    client_sender: &UnboundedSender<Message>,
+    client_sender: &ClientSender,
     v_state: &VolumeCommandSender,
     mut rx: UnboundedReceiver<VolumeResult<T>>,
 ) -> Result<(), Box<dyn Error>> {
-    let request_id = {
-        let id = command.get_request_id();
-        match id.is_empty() {
-            true => command.get_name(),
-            false => id,
-        }
-    };
+    let request_id = response_type(&command);
     send_command(command, v_state)?;
 
     let response = match rx.recv().await {
@@ -208,6 +409,17 @@ async fn handle_command_with_response<T: serde::Serialize>(
         .map_err(|e| e.to_string().into())
 }
 
+/// The request id, or the command name when there is none.
+fn response_type(command: &VolumeCommand) -> String {
+    or_name(command.get_request_id(), &command.get_name())
+}
+
+/// Same shape as the REST API errors, `{ "type": name, "error": message }`.
+fn send_error(client_sender: &ClientSender, name: &str, error: &str) {
+    let response = json!({ "type": name, "error": error }).to_string();
+    let _ = client_sender.send(response.into());
+}
+
 fn create_json_response<T: serde::Serialize>(name: &str, data: &T) -> String {
     json!({
         "type": name,
@@ -216,7 +428,7 @@ fn create_json_response<T: serde::Serialize>(name: &str, data: &T) -> String {
     .to_string()
 }
 
-fn parse_action(action: &str) -> Result<VolumeCommand, serde_json::Error> {
+fn parse_action(action: &str) -> Result<IncomingMessage, serde_json::Error> {
     println!("Parsing action: {}", action);
-    serde_json::from_str::<VolumeCommand>(action)
+    serde_json::from_str::<IncomingMessage>(action)
 }
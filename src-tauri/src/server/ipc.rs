@@ -0,0 +1,315 @@
+use std::error::Error;
+
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_util::sync::CancellationToken;
+
+use crate::types::volume::VolumeCommand;
+
+use super::{
+    incoming::handle_volume_command, queue::client_queue, runtime as rt, RunningServer,
+    ServerContext,
+};
+
+/// Local control endpoint for scripts running on this machine.
+/// - Unix: `volumize.sock` in the config directory, readable by the current user only.
+/// - Windows: the `\\.\pipe\volumize-<user>` named pipe, remote clients are rejected.
+///
+/// Speaks the WebSocket `VolumeCommand` JSON protocol, one message per line.
+/// There is no authentication, access is left to the OS permissions.
+pub fn start_ipc_server(context: &ServerContext) -> Result<String, Box<dyn Error>> {
+    let state = &context.ipc;
+    state.stop();
+
+    let cancel = CancellationToken::new();
+
+    #[cfg(unix)]
+    let (endpoint, handle) = {
+        let path = context.storage.config_dir().join(socket::SOCKET_NAME);
+        let listener = socket::bind(&path)?;
+
+        let endpoint = path.display().to_string();
+        let handle = rt::spawn(socket::accept_loop(
+            listener,
+            path,
+            cancel.clone(),
+            context.clone(),
+        ));
+        (endpoint, handle)
+    };
+
+    #[cfg(windows)]
+    let (endpoint, handle) = {
+        let name = pipe::pipe_name();
+        let first = pipe::create_pipe(&name, true)?;
+
+        let handle = rt::spawn(pipe::accept_loop(
+            first,
+            name.clone(),
+            cancel.clone(),
+            context.clone(),
+        ));
+        (name, handle)
+    };
+
+    state.set(RunningServer {
+        name: "IPC".into(),
+        handle,
+        cancel,
+    });
+
+    Ok(endpoint)
+}
+
+/// Serves one local client until it disconnects.
+/// - Parse and command errors are answered with `{ "type": "error", "error": message }`.
+async fn serve_connection<S>(stream: S, context: ServerContext)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (read, mut write) = tokio::io::split(stream);
+    let (sender, mut receiver) = client_queue();
+
+    let write_task = rt::spawn(async move {
+        while let Some(message) = receiver.recv().await {
+            let text = match message {
+                Message::Text(text) => text,
+                _ => continue,
+            };
+
+            let line = format!("{}\n", text.as_str());
+            if write.write_all(line.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut lines = BufReader::new(read).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let result = match serde_json::from_str::<VolumeCommand>(&line) {
+            Ok(command) => handle_volume_command(command, &sender, &context.volume)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+
+        if let Err(error) = result {
+            let response = json!({ "type": "error", "error": error }).to_string();
+            if sender.send(response.into()).is_err() {
+                break;
+            }
+        }
+    }
+
+    // Closing lets the writer flush what is queued and finish.
+    sender.close();
+    let _ = write_task.await;
+}
+
+#[cfg(unix)]
+mod socket {
+    use std::{
+        fs, io,
+        os::unix::{
+            fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+            net::UnixStream as StdUnixStream,
+        },
+        path::{Path, PathBuf},
+    };
+
+    use futures_util::future::{select, Either};
+
+    use super::{rt, ServerContext};
+    use tokio::net::UnixListener;
+    use tokio_util::sync::CancellationToken;
+
+    pub const SOCKET_NAME: &str = "volumize.sock";
+
+    /// Replaces a socket left behind by a previous run.
+    /// - Fails when another server still answers on it, or the path isn't a socket.
+    /// - Bound in a directory only the current user can enter and moved into place once
+    ///   it is `0600`, nobody else can connect in between.
+    pub fn bind(path: &Path) -> io::Result<UnixListener> {
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if !metadata.file_type().is_socket() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path.display()),
+                ));
+            }
+            Ok(_) => match StdUnixStream::connect(path) {
+                Ok(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        format!("Another server is listening on {}", path.display()),
+                    ));
+                }
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => fs::remove_file(path)?,
+                Err(e) => return Err(e),
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        // Left behind when a previous run with the same pid was killed halfway.
+        let private = path.with_extension(format!("{}.tmp", std::process::id()));
+        let _ = fs::remove_dir_all(&private);
+        fs::DirBuilder::new().mode(0o700).create(&private)?;
+
+        let staged = private.join(SOCKET_NAME);
+        let listener = UnixListener::bind(&staged).and_then(|listener| {
+            fs::set_permissions(&staged, fs::Permissions::from_mode(0o600))?;
+            fs::rename(&staged, path)?;
+            Ok(listener)
+        });
+
+        let _ = fs::remove_file(&staged);
+        let _ = fs::remove_dir(&private);
+        listener
+    }
+
+    pub async fn accept_loop(
+        listener: UnixListener,
+        path: PathBuf,
+        cancel: CancellationToken,
+        context: ServerContext,
+    ) {
+        loop {
+            let cancelled = cancel.cancelled();
+            let accept = listener.accept();
+
+            match select(Box::pin(cancelled), Box::pin(accept)).await {
+                Either::Left(_) => break,
+                Either::Right((Ok((stream, _)), _)) => {
+                    rt::spawn(super::serve_connection(stream, context.clone()));
+                }
+                Either::Right((Err(e), _)) => {
+                    eprintln!("[ipc] Failed to accept connection: {}", e);
+                    break;
+                }
+            }
+        }
+
+        let _ = fs::remove_file(&path);
+    }
+}
+
+#[cfg(windows)]
+mod pipe {
+    use futures_util::future::{select, Either};
+
+    use super::{rt, ServerContext};
+    use tokio::net::windows::named_pipe::{NamedPipeServer, ServerOptions};
+    use tokio_util::sync::CancellationToken;
+
+    /// Per user, so two sessions on the same machine don't collide.
+    pub fn pipe_name() -> String {
+        let user = std::env::var("USERNAME").unwrap_or_default();
+        format!(r"\\.\pipe\volumize-{}", user)
+    }
+
+    /// The default pipe security only grants write access to the creating user,
+    /// administrators and LocalSystem.
+    pub fn create_pipe(name: &str, first: bool) -> std::io::Result<NamedPipeServer> {
+        ServerOptions::new()
+            .first_pipe_instance(first)
+            .reject_remote_clients(true)
+            .create(name)
+    }
+
+    pub async fn accept_loop(
+        mut server: NamedPipeServer,
+        name: String,
+        cancel: CancellationToken,
+        context: ServerContext,
+    ) {
+        loop {
+            let cancelled = cancel.cancelled();
+            let connect = server.connect();
+
+            match select(Box::pin(cancelled), Box::pin(connect)).await {
+                Either::Left(_) => break,
+                Either::Right((Ok(()), _)) => {}
+                Either::Right((Err(e), _)) => {
+                    eprintln!("[ipc] Failed to accept connection: {}", e);
+                    break;
+                }
+            }
+
+            // Create the next instance before handing this one off, clients never see the pipe missing.
+            let next = match create_pipe(&name, false) {
+                Ok(next) => next,
+                Err(e) => {
+                    eprintln!("[ipc] Failed to create pipe instance: {}", e);
+                    break;
+                }
+            };
+
+            let connected = std::mem::replace(&mut server, next);
+            rt::spawn(super::serve_connection(connected, context.clone()));
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{
+        fs,
+        os::unix::{fs::PermissionsExt, net::UnixListener as StdUnixListener},
+        path::PathBuf,
+    };
+
+    use super::socket;
+
+    fn socket_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("volumize-ipc-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn replaces_a_stale_socket() {
+        let path = socket_path("stale.sock");
+        drop(StdUnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let _listener = socket::bind(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // Reachable where it was moved, the private directory is gone.
+        assert!(std::os::unix::net::UnixStream::connect(&path).is_ok());
+        let private = path.with_extension(format!("{}.tmp", std::process::id()));
+        assert!(!private.exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_a_socket_in_use() {
+        let path = socket_path("live.sock");
+        let _running = StdUnixListener::bind(&path).unwrap();
+
+        let error = socket::bind(&path).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::AddrInUse);
+        assert!(path.exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_other_files() {
+        let path = socket_path("file.sock");
+        fs::write(&path, b"not a socket").unwrap();
+
+        assert!(socket::bind(&path).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"not a socket");
+        fs::remove_file(&path).unwrap();
+    }
+}
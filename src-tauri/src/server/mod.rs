@@ -1,38 +1,128 @@
 use std::{
     collections::HashMap,
-    net::{Ipv4Addr, SocketAddrV4},
-    sync::{Arc, Mutex},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use futures_util::future::{select, Either};
+use futures_util::future::select_all;
 use serde::{Deserialize, Serialize};
-use tauri::{
-    async_runtime::{self as rt},
-    AppHandle, Manager,
+use serde_json::json;
+use tokio::{
+    net::{TcpListener as TokioTcpListener, TcpStream},
+    sync::{
+        mpsc::{unbounded_channel, UnboundedSender},
+        oneshot,
+    },
+    task::JoinSet,
 };
-use tokio::{net::TcpListener as TokioTcpListener, sync::mpsc::UnboundedSender, task::JoinSet};
 use tokio_tungstenite::tungstenite::Message;
 use tokio_util::sync::CancellationToken;
 
+use crate::types::{shared::SERVER_MOVED_EVENT_NAME, storage::Settings, tray::Discovery};
+use runtime as rt;
+
+mod bind;
+pub mod bundle;
+mod context;
+mod deflate;
 mod handle;
+mod http;
 mod incoming;
+pub mod ipc;
+pub mod profiles;
+mod queue;
+mod rest;
+pub mod rules;
+mod runtime;
 pub mod service_discovery;
 pub mod service_register;
+pub mod settings;
+mod sse;
 pub mod volume_control;
 
+pub use context::{NoEvents, ServerContext, ServerEvents};
+use queue::ClientSender;
+
+pub const PROTOCOL_VERSION: u32 = 1;
+const PORT_FALLBACK_RANGE: u16 = 10;
+const CLOSE_GRACE_PERIOD: Duration = Duration::from_secs(2);
+/// Pause after a failed accept, such as running out of file descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ClientInfo {
     pub id: String,
     pub address: String,
+    /// Unix timestamp in milliseconds.
+    pub connected_at: u64,
+    pub device_name: Option<String>,
+    pub protocol_version: Option<u32>,
+    pub messages_received: u64,
+    pub messages_sent: u64,
+    /// Unix timestamp in milliseconds of the last frame received.
+    pub last_seen: u64,
+    /// Round-trip time of the last heartbeat ping.
+    pub latency_ms: Option<u64>,
+    /// Set once `client_connected` was sent, shared by every copy.
+    #[serde(skip)]
+    announced: Arc<AtomicBool>,
+}
+
+impl ClientInfo {
+    pub fn new(id: String, address: SocketAddr) -> Self {
+        let connected_at = unix_millis();
+
+        Self {
+            id,
+            address: address.to_string(),
+            connected_at,
+            device_name: None,
+            protocol_version: None,
+            messages_received: 0,
+            messages_sent: 0,
+            last_seen: connected_at,
+            latency_ms: None,
+            announced: Default::default(),
+        }
+    }
+
+    /// True for the first caller only.
+    pub fn mark_announced(&self) -> bool {
+        !self.announced.swap(true, Ordering::Relaxed)
+    }
+
+    pub fn is_announced(&self) -> bool {
+        self.announced.load(Ordering::Relaxed)
+    }
+
+    /// Declared device name, falls back to the address.
+    pub fn display_name(&self) -> &str {
+        match &self.device_name {
+            Some(name) if !name.is_empty() => name,
+            _ => &self.address,
+        }
+    }
+}
+
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis() as u64)
+        .unwrap_or(0)
 }
 
-type ClientSender = UnboundedSender<Message>;
 type ClientMap = Arc<rt::Mutex<HashMap<String, (ClientInfo, ClientSender)>>>;
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct WebSocketServerState {
     clients: ClientMap,
-    server: Arc<rt::Mutex<Option<RunningServer>>>,
+    server: Arc<rt::Mutex<Option<WebSocketServer>>>,
+    addresses: Arc<Mutex<Vec<SocketAddr>>>,
+    updates: Arc<sse::UpdateLog>,
 }
 
 pub struct RunningServer {
@@ -50,20 +140,92 @@ impl RunningServer {
     }
 }
 
-#[derive(Default)]
+/// Listeners handed to the WebSocket server task.
+enum Listen {
+    /// Accept connections on these from now on.
+    Start(Vec<std::net::TcpListener>),
+    /// Close the listeners, answered once they are closed.
+    Release(oneshot::Sender<()>),
+}
+
+/// The WebSocket server task, its listeners can be swapped while clients stay connected.
+struct WebSocketServer {
+    task: RunningServer,
+    listen: UnboundedSender<Listen>,
+}
+
+impl WebSocketServer {
+    fn start(&self, listeners: Vec<std::net::TcpListener>) {
+        let _ = self.listen.send(Listen::Start(listeners));
+    }
+
+    /// Blocking, not to be called from the async runtime.
+    fn release(&self) {
+        let (done, released) = oneshot::channel();
+        if self.listen.send(Listen::Release(done)).is_ok() {
+            let _ = released.blocking_recv();
+        }
+    }
+}
+
+/// What service discovery is doing right now.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct DiscoveryStatus {
+    /// Policy in effect, `TurnOff` once an `OnDuration` window has closed.
+    pub policy: Discovery,
+    /// Unix timestamp in milliseconds when the `OnDuration` window closes.
+    pub expires_at: Option<u64>,
+    /// Policy last chosen, kept after its window closed so it can be started again.
+    pub chosen: Discovery,
+}
+
+impl Default for DiscoveryStatus {
+    fn default() -> Self {
+        Self {
+            policy: Discovery::TurnOff,
+            expires_at: None,
+            chosen: Discovery::TurnOff,
+        }
+    }
+}
+
+impl DiscoveryStatus {
+    pub fn remaining(&self) -> Option<Duration> {
+        self.expires_at
+            .map(|at| Duration::from_millis(at.saturating_sub(unix_millis())))
+    }
+}
+
+#[derive(Default, Clone)]
 pub struct ServiceDiscovery {
     server: Arc<Mutex<Option<RunningServer>>>,
+    status: Arc<Mutex<DiscoveryStatus>>,
 }
 
 impl ServiceDiscovery {
     const LISTEN_PORT: u16 = 31280;
     pub const MDNS_DOMAIN: &str = "_volume-service._tcp.local.";
-    pub const MDNS_INSTANCE_NAME: &str = "volumize";
     pub const DISCOVERY_MSG: &str = "DISCOVER_VOLUMIZE";
     pub const BROADCAST_ADDRESS: SocketAddrV4 =
         SocketAddrV4::new(Ipv4Addr::BROADCAST, Self::LISTEN_PORT);
-    pub const LISTEN_ADDRESS: SocketAddrV4 =
-        SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, Self::LISTEN_PORT);
+    /// Link-local group the IPv6 discovery sockets join, IPv6 has no broadcast.
+    pub const MULTICAST_GROUP: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0x766f, 0x6c6d);
+    pub const MULTICAST_ADDRESS: SocketAddrV6 =
+        SocketAddrV6::new(Self::MULTICAST_GROUP, Self::LISTEN_PORT, 0, 0);
+
+    pub fn status(&self) -> DiscoveryStatus {
+        match self.status.lock() {
+            Ok(status) => *status,
+            Err(e) => *e.into_inner(),
+        }
+    }
+
+    fn set_status(&self, value: DiscoveryStatus) {
+        match self.status.lock() {
+            Ok(mut status) => *status = value,
+            Err(e) => *e.into_inner() = value,
+        }
+    }
 
     pub async fn shutdown(&self) -> Result<(), String> {
         let mut server = match self.server.lock() {
@@ -77,72 +239,340 @@ impl ServiceDiscovery {
     }
 }
 
+#[derive(Default, Clone)]
+pub struct IpcServerState {
+    server: Arc<Mutex<Option<RunningServer>>>,
+}
+
+impl IpcServerState {
+    fn set(&self, value: RunningServer) {
+        match self.server.lock() {
+            Ok(mut server) => *server = Some(value),
+            Err(e) => *e.into_inner() = Some(value),
+        }
+    }
+
+    /// Blocking, not to be called from the async runtime.
+    fn stop(&self) {
+        if let Err(e) = rt::block_on(self.shutdown()) {
+            eprintln!("{}", e);
+        }
+    }
+
+    pub async fn shutdown(&self) -> Result<(), String> {
+        let server = match self.server.lock() {
+            Ok(mut server) => server.take(),
+            Err(e) => e.into_inner().take(),
+        };
+        match server {
+            Some(server) => server.shutdown().await,
+            None => Ok(()),
+        }
+    }
+}
+
 impl WebSocketServerState {
+    pub async fn list_clients(&self) -> Vec<ClientInfo> {
+        list_clients(&self.clients).await
+    }
+
+    pub async fn disconnect_client(&self, id: &str) -> Result<ClientInfo, String> {
+        disconnect_client(&self.clients, id).await
+    }
+
+    /// Addresses the listeners are actually bound to, the port may differ from the settings.
+    pub fn local_addresses(&self) -> Vec<SocketAddr> {
+        match self.addresses.lock() {
+            Ok(addresses) => addresses.clone(),
+            Err(e) => e.into_inner().clone(),
+        }
+    }
+
+    pub fn local_port(&self) -> Option<u16> {
+        self.local_addresses().first().map(|addr| addr.port())
+    }
+
+    fn set_local_addresses(&self, value: Vec<SocketAddr>) {
+        match self.addresses.lock() {
+            Ok(mut addresses) => *addresses = value,
+            Err(e) => *e.into_inner() = value,
+        }
+    }
+
     pub async fn shutdown(&self) -> Result<(), String> {
         let mut server = self.server.lock().await;
         if let Some(server) = server.take() {
-            server.shutdown().await?
+            close_all_clients(&mut *self.clients.lock().await, None);
+            server.task.shutdown().await?
         }
 
+        self.set_local_addresses(vec![]);
         self.clients.lock().await.clear();
         Ok(())
     }
 }
 
-pub fn start_websocket_server(
+async fn list_clients(clients: &ClientMap) -> Vec<ClientInfo> {
+    let mut list: Vec<ClientInfo> = clients
+        .lock()
+        .await
+        .values()
+        .map(|(info, _)| info.clone())
+        .collect();
+
+    list.sort_by_key(|info| info.connected_at);
+    list
+}
+
+/// Removes the client from the map and asks it to close the connection.
+/// - Dropping the sender ends the outgoing task, which tears down the connection.
+async fn disconnect_client(clients: &ClientMap, id: &str) -> Result<ClientInfo, String> {
+    let (info, sender) = match clients.lock().await.remove(id) {
+        Some(client) => client,
+        None => return Err(format!("Client not found: {}", id)),
+    };
+
+    let _ = sender.send(Message::Close(None));
+    sender.close();
+    Ok(info)
+}
+
+/// Sends an optional notice followed by a close frame to every client.
+fn close_all_clients(
+    clients: &mut HashMap<String, (ClientInfo, ClientSender)>,
+    notice: Option<Message>,
+) {
+    for (_, (_, sender)) in clients.drain() {
+        if let Some(notice) = &notice {
+            let _ = sender.send(notice.clone());
+        }
+        let _ = sender.send(Message::Close(None));
+        sender.close();
+    }
+}
+
+/// Binds every address on `port`, fails as a whole when the port is taken on one of them.
+/// - Addresses that cannot be bound for other reasons, like a missing IPv6 stack, are skipped.
+fn bind_listeners(addresses: &[IpAddr], port: u16) -> std::io::Result<Vec<std::net::TcpListener>> {
+    let mut listeners = vec![];
+
+    for ip in addresses {
+        match bind::tcp_listener(SocketAddr::new(*ip, port)) {
+            Ok(listener) => listeners.push(listener),
+            Err(e) if bind::is_port_error(&e) => return Err(e),
+            Err(e) => eprintln!("[bind_listeners] Skipping {}: {}", ip, e),
+        }
+    }
+
+    match listeners.is_empty() {
+        true => Err(std::io::ErrorKind::AddrNotAvailable.into()),
+        false => Ok(listeners),
+    }
+}
+
+/// Tries `port` first, then the next `PORT_FALLBACK_RANGE` ports when `fallback` is set.
+fn bind_with_fallback(
+    addresses: &[IpAddr],
     port: u16,
-    app_handle: &AppHandle,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port);
+    fallback: bool,
+) -> std::io::Result<Vec<std::net::TcpListener>> {
+    let last_port = match fallback {
+        true => port.saturating_add(PORT_FALLBACK_RANGE),
+        false => port,
+    };
+
+    let mut last_error = None;
+    for candidate in port..=last_port {
+        match bind_listeners(addresses, candidate) {
+            Ok(listeners) => return Ok(listeners),
+            Err(e) if bind::is_port_error(&e) => {
+                eprintln!("[bind_with_fallback] Port {} unavailable: {}", candidate, e);
+                last_error = Some(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
 
-    let state = app_handle.state::<WebSocketServerState>();
-    let clients = state.clients.clone();
-    let app_handle_clone = app_handle.clone();
+    Err(last_error.unwrap_or_else(|| std::io::ErrorKind::AddrInUse.into()))
+}
+
+/// Binds the addresses in `settings` and hands them to the server, starting it when needed.
+/// - The old listeners are closed first, the new addresses may overlap them.
+/// - When binding fails the old addresses are bound again and clients stay connected.
+/// - Clients are told the new port and closed when the server moved.
+/// - Blocks on the server locks, not to be called from the async runtime.
+pub fn start_websocket_server(
+    settings: &Settings,
+    context: &ServerContext,
+) -> Result<Vec<SocketAddr>, Box<dyn std::error::Error>> {
+    let state = &context.websocket;
+    let bind_addresses = bind::resolve_bind_addresses(&settings.bind_addresses)?;
+
+    let mut current_server = state.server.blocking_lock();
+    let server = current_server.get_or_insert_with(|| spawn_websocket_server(context));
+
+    let previous = state.local_addresses();
+    if !previous.is_empty() {
+        server.release();
+        state.set_local_addresses(vec![]);
+    }
+
+    let listeners = match bind_with_fallback(
+        &bind_addresses,
+        settings.port_address,
+        settings.port_fallback,
+    ) {
+        Ok(listeners) => listeners,
+        Err(e) => {
+            if !previous.is_empty() {
+                rebind(server, state, previous);
+            }
+            return Err(e.into());
+        }
+    };
+
+    let addresses: Vec<SocketAddr> = listeners
+        .iter()
+        .filter_map(|listener| listener.local_addr().ok())
+        .collect();
+    server.start(listeners);
+    state.set_local_addresses(addresses.clone());
+
+    if !previous.is_empty() && previous != addresses {
+        let port = addresses
+            .first()
+            .map_or(settings.port_address, |addr| addr.port());
+        let notice = json!({
+            "event": SERVER_MOVED_EVENT_NAME,
+            "payload": { "port": port }
+        })
+        .to_string();
+
+        close_all_clients(&mut *state.clients.blocking_lock(), Some(notice.into()));
+    }
+
+    Ok(addresses)
+}
+
+/// Goes back to the addresses the server was bound to before a failed move.
+fn rebind(server: &WebSocketServer, state: &WebSocketServerState, previous: Vec<SocketAddr>) {
+    let listeners = previous
+        .iter()
+        .map(|addr| bind::tcp_listener(*addr))
+        .collect::<std::io::Result<Vec<_>>>();
+
+    match listeners {
+        Ok(listeners) => {
+            server.start(listeners);
+            state.set_local_addresses(previous);
+        }
+        Err(e) => eprintln!("[rebind] Failed to bind {:?} again: {}", previous, e),
+    }
+}
+
+/// Accepts connections on the listeners it was last given, see `Listen`.
+fn spawn_websocket_server(context: &ServerContext) -> WebSocketServer {
+    let context = context.clone();
+    let (listen, mut requests) = unbounded_channel::<Listen>();
 
     let cancel = CancellationToken::new();
     let cancel_clone = cancel.clone();
 
-    let std_listener = std::net::TcpListener::bind(addr)?;
-    std_listener
-        .set_nonblocking(true)
-        .expect("Cannot set non-blocking");
-
-    let new_handle = rt::spawn(async move {
-        let async_listener = TokioTcpListener::from_std(std_listener)
-            .expect("Failed to convert tokio listener into std");
+    let handle = rt::spawn(async move {
+        let mut listeners: Vec<TokioTcpListener> = vec![];
         let mut conns = JoinSet::new();
 
         loop {
-            let cancelled = cancel_clone.cancelled();
-            let accept = async_listener.accept();
-
-            match select(Box::pin(cancelled), Box::pin(accept)).await {
-                Either::Left(_) => break,
-                Either::Right((Ok((stream, peer_addr)), _)) => {
-                    conns.spawn(handle::handle_client(
-                        stream,
-                        peer_addr,
-                        clients.clone(),
-                        app_handle_clone.clone(),
-                    ));
-                }
-                Either::Right((Err(_), _)) => break,
-            };
+            tokio::select! {
+                _ = cancel_clone.cancelled() => break,
+                request = requests.recv() => match request {
+                    Some(Listen::Start(std_listeners)) => listeners = into_tokio(std_listeners),
+                    Some(Listen::Release(done)) => {
+                        listeners.clear();
+                        let _ = done.send(());
+                    }
+                    None => break,
+                },
+                accepted = accept_any(&listeners) => match accepted {
+                    Ok((stream, peer_addr)) => {
+                        // Read per connection, settings changes apply without a restart.
+                        let settings = context.storage.get();
+                        conns.spawn(handle::handle_client(
+                            stream,
+                            peer_addr,
+                            settings.heartbeat,
+                            settings.compression,
+                            context.clone(),
+                        ));
+                    }
+                    // Only this connection failed, the listeners keep going.
+                    Err(e) => {
+                        eprintln!("[websocket] Failed to accept connection: {}", e);
+                        tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    }
+                },
+            }
         }
+        drop(listeners);
 
+        // Give connections a moment to flush their close frames.
+        let drain = async { while conns.join_next().await.is_some() {} };
+        let _ = tokio::time::timeout(CLOSE_GRACE_PERIOD, drain).await;
         conns.shutdown().await;
     });
 
-    // Store the server handle
-    let new_server = RunningServer {
-        name: "Websocket".into(),
-        handle: new_handle,
-        cancel,
-    };
-    let mut current_server = state.server.blocking_lock();
-    if let Some(old) = current_server.replace(new_server) {
-        rt::spawn(old.shutdown());
+    WebSocketServer {
+        task: RunningServer {
+            name: "Websocket".into(),
+            handle,
+            cancel,
+        },
+        listen,
+    }
+}
+
+fn into_tokio(listeners: Vec<std::net::TcpListener>) -> Vec<TokioTcpListener> {
+    listeners
+        .into_iter()
+        .filter_map(|listener| match TokioTcpListener::from_std(listener) {
+            Ok(listener) => Some(listener),
+            Err(e) => {
+                eprintln!("[into_tokio] Failed to register listener: {}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Never finishes while there are no listeners.
+async fn accept_any(listeners: &[TokioTcpListener]) -> std::io::Result<(TcpStream, SocketAddr)> {
+    if listeners.is_empty() {
+        return std::future::pending().await;
+    }
+
+    let (accepted, _, _) = select_all(listeners.iter().map(|l| Box::pin(l.accept()))).await;
+    accepted
+}
+
+/// Moves the server when the port, fallback or bind addresses differ from `current`.
+/// - A server that fell back to another port stays there while they are the same.
+/// - Started again when it isn't running, a bind that failed earlier is retried.
+pub fn restart_websocket_server(
+    current: &Settings,
+    new: &Settings,
+    context: &ServerContext,
+) -> Result<Vec<SocketAddr>, Box<dyn std::error::Error>> {
+    let bound = context.websocket.local_addresses();
+    if !bind_settings_changed(current, new) && !bound.is_empty() {
+        return Ok(bound);
     }
 
-    Ok(addr.to_string())
+    start_websocket_server(new, context)
+}
+
+/// The server moves and closes its clients when applying `new`, see `restart_websocket_server`.
+pub fn bind_settings_changed(current: &Settings, new: &Settings) -> bool {
+    current.port_address != new.port_address
+        || current.port_fallback != new.port_fallback
+        || current.bind_addresses != new.bind_addresses
 }
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+
+use shared_types::AudioVolume;
+
+use crate::types::{
+    profiles::{Profile, ProfileEntry, ProfileReport},
+    volume::{VolumeCommand, VolumeCommandSender},
+};
+
+use super::{
+    volume_control::{playback_devices, running_apps},
+    ServerContext,
+};
+
+const MAX_PROFILE_NAME_LENGTH: usize = 64;
+
+/// Names are trimmed before they get here.
+pub fn check_profile_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.chars().count() > MAX_PROFILE_NAME_LENGTH {
+        return Err(format!(
+            "Profile name must be between 1 and {} characters",
+            MAX_PROFILE_NAME_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+/// Saves the current volume of every playback device and running app as `name`.
+/// - Apps are stored by executable path, apps without one are left out.
+/// - Replaces a profile with the same name.
+pub async fn save_profile(context: &ServerContext, name: &str) -> Result<Profile, String> {
+    let name = name.trim();
+    check_profile_name(name)?;
+
+    let devices = playback_devices(&context.volume).await?;
+    let apps = running_apps(&context.volume, &devices).await;
+
+    let mut profile = Profile {
+        name: name.to_string(),
+        devices: devices
+            .iter()
+            .map(|device| entry(device.id.clone(), &device.name, &device.volume))
+            .collect(),
+        apps: vec![],
+    };
+
+    for app in &apps {
+        let path = match &app.process.path {
+            Some(path) if !path.is_empty() => path,
+            _ => continue,
+        };
+        // An app with several sessions is only stored once.
+        if profile.apps.iter().any(|saved| &saved.id == path) {
+            continue;
+        }
+        profile
+            .apps
+            .push(entry(path.clone(), &app.process.name, &app.volume));
+    }
+
+    context
+        .profiles
+        .insert(profile.clone())
+        .map_err(|e| e.to_string())?;
+    context.events.profiles_changed(&context.profiles.names());
+    Ok(profile)
+}
+
+fn entry(id: String, name: &str, volume: &AudioVolume) -> ProfileEntry {
+    ProfileEntry {
+        id,
+        name: name.to_string(),
+        volume: volume.current,
+        mute: volume.muted,
+    }
+}
+
+/// Sets every device and app in the profile that is around right now.
+/// - Devices that aren't connected and apps that aren't running are reported as skipped.
+pub async fn apply_profile(context: &ServerContext, name: &str) -> Result<ProfileReport, String> {
+    let profile = match context.profiles.get(name).map_err(|e| e.to_string())? {
+        Some(profile) => profile,
+        None => return Err(format!("No profile named \"{}\"", name)),
+    };
+
+    let devices = playback_devices(&context.volume).await?;
+    let apps = running_apps(&context.volume, &devices).await;
+
+    let mut report = ProfileReport {
+        profile: profile.name.clone(),
+        ..Default::default()
+    };
+
+    for saved in &profile.devices {
+        if !devices.iter().any(|device| device.id == saved.id) {
+            report.skipped.push(saved.name.clone());
+            continue;
+        }
+
+        let id = saved.id.clone();
+        let commands = [
+            VolumeCommand::DeviceSetVolume {
+                request_id: String::new(),
+                id: id.clone(),
+                volume: saved.volume,
+            },
+            match saved.mute {
+                true => VolumeCommand::DeviceMute {
+                    request_id: String::new(),
+                    id,
+                },
+                false => VolumeCommand::DeviceUnmute {
+                    request_id: String::new(),
+                    id,
+                },
+            },
+        ];
+        send_all(&context.volume, commands, saved, &mut report);
+    }
+
+    let mut pids: HashMap<&str, Vec<_>> = HashMap::new();
+    for app in &apps {
+        if let Some(path) = &app.process.path {
+            pids.entry(path.as_str()).or_default().push(app.process.id);
+        }
+    }
+
+    for saved in &profile.apps {
+        let app_pids = match pids.get(saved.id.as_str()) {
+            Some(app_pids) => app_pids,
+            None => {
+                report.skipped.push(saved.name.clone());
+                continue;
+            }
+        };
+
+        let commands = app_pids.iter().flat_map(|&id| {
+            [
+                VolumeCommand::ApplicationSetVolume {
+                    request_id: String::new(),
+                    id,
+                    volume: saved.volume,
+                },
+                match saved.mute {
+                    true => VolumeCommand::ApplicationMute {
+                        request_id: String::new(),
+                        id,
+                    },
+                    false => VolumeCommand::ApplicationUnmute {
+                        request_id: String::new(),
+                        id,
+                    },
+                },
+            ]
+        });
+        send_all(&context.volume, commands, saved, &mut report);
+    }
+
+    Ok(report)
+}
+
+fn send_all(
+    volume: &VolumeCommandSender,
+    commands: impl IntoIterator<Item = VolumeCommand>,
+    saved: &ProfileEntry,
+    report: &mut ProfileReport,
+) {
+    let mut sent = true;
+    for command in commands {
+        if let Err(e) = volume.send(command) {
+            eprintln!("[apply_profile] {}: {}", saved.name, e);
+            sent = false;
+        }
+    }
+
+    match sent {
+        true => report.applied.push(saved.name.clone()),
+        false => report.skipped.push(saved.name.clone()),
+    }
+}
+
+pub fn delete_profile(context: &ServerContext, name: &str) -> Result<(), String> {
+    match context.profiles.remove(name) {
+        Ok(true) => {
+            context.events.profiles_changed(&context.profiles.names());
+            Ok(())
+        }
+        Ok(false) => Err(format!("No profile named \"{}\"", name)),
+        Err(e) => Err(e.to_string()),
+    }
+}
@@ -0,0 +1,296 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use thiserror::Error;
+use tokio::sync::Notify;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::types::shared::Identifier;
+
+#[derive(Debug, Error)]
+pub enum QueueError {
+    #[error("Client queue is closed")]
+    Closed,
+    #[error("Client queue stayed full for longer than {0:?}")]
+    Saturated(Duration),
+    #[error("Client queue is full of messages that can't be dropped ({0})")]
+    Full(usize),
+}
+
+struct Queued {
+    message: Message,
+    collapse_key: Option<Identifier>,
+}
+
+#[derive(Default)]
+struct QueueState {
+    items: VecDeque<Queued>,
+    closed: bool,
+    full_since: Option<Instant>,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<QueueState>,
+    notify: Notify,
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, QueueState> {
+        match self.state.lock() {
+            Ok(value) => value,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    fn close(&self) {
+        let mut state = self.state();
+        state.closed = true;
+        state.items.clear();
+        self.notify.notify_one();
+    }
+}
+
+/// Bounded outgoing queue for a single WebSocket client.
+///
+/// Never holds more than `CAPACITY` messages, when it is full:
+/// - Volume events replace the queued events for the same `Identifier`, the new one
+///   goes to the back.
+/// - Otherwise the oldest queued volume event makes room.
+/// - A volume event with no room left is dropped.
+/// - Responses and state events are never dropped, a client whose queue is full
+///   of them is closed.
+/// - A client that stays full for `SATURATION_LIMIT` is closed.
+pub fn client_queue() -> (ClientSender, ClientReceiver) {
+    let shared = Arc::new(Shared::default());
+    (
+        ClientSender {
+            shared: shared.clone(),
+        },
+        ClientReceiver { shared },
+    )
+}
+
+#[derive(Clone)]
+pub struct ClientSender {
+    shared: Arc<Shared>,
+}
+
+impl ClientSender {
+    pub const CAPACITY: usize = 256;
+    pub const SATURATION_LIMIT: Duration = Duration::from_secs(10);
+
+    /// Queue a message that must be delivered, such as a response.
+    pub fn send(&self, message: Message) -> Result<(), QueueError> {
+        self.push(message, None)
+    }
+
+    /// Queue a message that a newer one for the same `id` may supersede.
+    pub fn send_collapsible(&self, id: Identifier, message: Message) -> Result<(), QueueError> {
+        self.push(message, Some(id))
+    }
+
+    /// Stops accepting messages, already queued messages are still delivered.
+    pub fn close(&self) {
+        let mut state = self.shared.state();
+        state.closed = true;
+        self.shared.notify.notify_one();
+    }
+
+    fn push(&self, message: Message, collapse_key: Option<Identifier>) -> Result<(), QueueError> {
+        let mut state = self.shared.state();
+        if state.closed {
+            return Err(QueueError::Closed);
+        }
+
+        if state.items.len() < Self::CAPACITY {
+            state.full_since = None;
+        } else {
+            let full_since = *state.full_since.get_or_insert_with(Instant::now);
+            if full_since.elapsed() > Self::SATURATION_LIMIT {
+                drop(state);
+                self.shared.close();
+                return Err(QueueError::Saturated(Self::SATURATION_LIMIT));
+            }
+
+            if let Some(key) = &collapse_key {
+                state
+                    .items
+                    .retain(|queued| queued.collapse_key.as_ref() != Some(key));
+            }
+
+            if state.items.len() >= Self::CAPACITY {
+                let oldest = state
+                    .items
+                    .iter()
+                    .position(|queued| queued.collapse_key.is_some());
+                match (oldest, &collapse_key) {
+                    (Some(index), _) => {
+                        state.items.remove(index);
+                    }
+                    (None, Some(_)) => return Ok(()),
+                    (None, None) => {
+                        drop(state);
+                        self.shared.close();
+                        return Err(QueueError::Full(Self::CAPACITY));
+                    }
+                }
+            }
+        }
+
+        state.items.push_back(Queued {
+            message,
+            collapse_key,
+        });
+        self.shared.notify.notify_one();
+        Ok(())
+    }
+}
+
+pub struct ClientReceiver {
+    shared: Arc<Shared>,
+}
+
+impl ClientReceiver {
+    /// Returns `None` once the queue is closed and drained.
+    pub async fn recv(&mut self) -> Option<Message> {
+        loop {
+            {
+                let mut state = self.shared.state();
+                if let Some(queued) = state.items.pop_front() {
+                    if state.items.len() < ClientSender::CAPACITY {
+                        state.full_since = None;
+                    }
+                    return Some(queued.message);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+
+            self.shared.notify.notified().await;
+        }
+    }
+}
+
+impl Drop for ClientReceiver {
+    fn drop(&mut self) {
+        self.shared.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shared_types::AppIdentifier;
+
+    use super::*;
+
+    fn volume_event(pid: AppIdentifier, text: &str) -> (Identifier, Message) {
+        (Identifier::App(pid), Message::from(text))
+    }
+
+    fn queued(sender: &ClientSender) -> Vec<Message> {
+        let state = sender.shared.state();
+        state
+            .items
+            .iter()
+            .map(|queued| queued.message.clone())
+            .collect()
+    }
+
+    #[test]
+    fn collapses_volume_events_only_when_full() {
+        let (sender, _receiver) = client_queue();
+
+        let (id, message) = volume_event(1, "first");
+        sender.send_collapsible(id.clone(), message).unwrap();
+        sender.send_collapsible(id, "second".into()).unwrap();
+        assert_eq!(queued(&sender).len(), 2);
+
+        for i in 2..ClientSender::CAPACITY {
+            sender.send(format!("response {}", i).into()).unwrap();
+        }
+        sender
+            .send_collapsible(Identifier::App(1), "third".into())
+            .unwrap();
+
+        let messages = queued(&sender);
+        assert_eq!(messages.len(), ClientSender::CAPACITY - 1);
+        assert!(!messages.contains(&Message::from("first")));
+        assert!(!messages.contains(&Message::from("second")));
+        assert_eq!(messages.last(), Some(&Message::from("third")));
+    }
+
+    #[test]
+    fn evicts_the_oldest_volume_event_at_capacity() {
+        let (sender, _receiver) = client_queue();
+
+        let (id, message) = volume_event(1, "volume");
+        sender.send_collapsible(id, message).unwrap();
+        for i in 1..ClientSender::CAPACITY {
+            sender.send(format!("response {}", i).into()).unwrap();
+        }
+
+        sender.send("state".into()).unwrap();
+
+        let messages = queued(&sender);
+        assert_eq!(messages.len(), ClientSender::CAPACITY);
+        assert!(!messages.contains(&Message::from("volume")));
+        assert_eq!(messages.last(), Some(&Message::from("state")));
+    }
+
+    #[test]
+    fn closes_when_full_of_messages_that_must_be_kept() {
+        let (sender, _receiver) = client_queue();
+
+        for i in 0..ClientSender::CAPACITY {
+            sender.send(format!("response {}", i).into()).unwrap();
+        }
+
+        // A volume event is dropped, the client stays open.
+        sender
+            .send_collapsible(Identifier::App(1), "volume".into())
+            .unwrap();
+        assert!(!queued(&sender).contains(&Message::from("volume")));
+
+        let result = sender.send("response".into());
+        assert!(matches!(result, Err(QueueError::Full(_))));
+        assert!(matches!(
+            sender.send("late".into()),
+            Err(QueueError::Closed)
+        ));
+    }
+
+    #[test]
+    fn closes_after_staying_full_too_long() {
+        let (sender, _receiver) = client_queue();
+
+        for i in 0..ClientSender::CAPACITY {
+            let (id, message) = volume_event(i as AppIdentifier, "volume");
+            sender.send_collapsible(id, message).unwrap();
+        }
+        sender.send("response".into()).unwrap();
+
+        let long_ago = Instant::now().checked_sub(ClientSender::SATURATION_LIMIT * 2);
+        sender.shared.state().full_since = long_ago;
+
+        let result = sender.send("response".into());
+        assert!(matches!(result, Err(QueueError::Saturated(_))));
+    }
+
+    #[tokio::test]
+    async fn delivers_queued_messages_after_close() {
+        let (sender, mut receiver) = client_queue();
+
+        sender.send("first".into()).unwrap();
+        sender.send("second".into()).unwrap();
+        sender.close();
+
+        assert_eq!(receiver.recv().await, Some(Message::from("first")));
+        assert_eq!(receiver.recv().await, Some(Message::from("second")));
+        assert_eq!(receiver.recv().await, None);
+    }
+}
@@ -0,0 +1,420 @@
+use serde::Deserialize;
+use serde_json::json;
+use tokio::net::TcpStream;
+
+use shared_types::{AppIdentifier, VolumePercent};
+
+use crate::types::{
+    shared::VolumeControllerError,
+    volume::{VolumeCommand, VolumeCommandSender},
+};
+
+use super::{
+    http::{self, HttpRequest, HttpResponse},
+    sse,
+    volume_control::{request, running_apps},
+    ServerContext,
+};
+
+/// REST endpoints served next to the WebSocket upgrade path.
+///
+/// - `GET  /api/devices`
+/// - `GET  /api/devices/{id}/applications`
+/// - `GET  /api/devices/{id}/volume`
+/// - `PUT  /api/devices/{id}/volume`  body: `{ "volume": 0.5 }`
+/// - `PUT  /api/devices/{id}/mute`    body: `{ "mute": true }`
+/// - `GET  /api/applications`
+/// - `GET  /api/applications/{id}`
+/// - `GET  /api/applications/{id}/volume`
+/// - `GET  /api/applications/{id}/icon`
+/// - `PUT  /api/applications/{id}/volume`
+/// - `PUT  /api/applications/{id}/mute`
+///
+/// - `GET  /api/events`               Server-Sent Events, see `sse::stream_events`
+///
+/// `POST` is accepted wherever `PUT` is. Responses use the WebSocket shape,
+/// `{ "type": name, "data": data }`, errors are `{ "type": name, "error": message }`.
+///
+/// Bodies have to be sent as `application/json` and there are no CORS headers,
+/// a web page on another origin can neither change volumes nor read the responses.
+pub async fn handle_rest_request(mut stream: TcpStream, head: Vec<u8>, context: ServerContext) {
+    let response = match http::read_request(&mut stream, &head).await {
+        Ok(request) if request.method == "GET" && request.segments() == ["api", "events"] => {
+            match sse::parse_filters(&request) {
+                Ok(filters) => return sse::stream_events(stream, request, filters, context).await,
+                Err(e) => ApiError::BadRequest(e).into_response("events"),
+            }
+        }
+        Ok(request) => route(request, &context.volume).await,
+        Err(e) => ApiError::BadRequest(e.to_string()).into_response("request"),
+    };
+
+    if let Err(e) = response.write_to(&mut stream).await {
+        eprintln!("Error writing REST response: {}", e);
+    }
+}
+
+#[derive(Debug)]
+enum ApiError {
+    BadRequest(String),
+    NotFound(String),
+    MethodNotAllowed,
+    UnsupportedMediaType,
+    Volume(VolumeControllerError),
+    Unavailable(String),
+}
+
+impl ApiError {
+    fn into_response(self, name: &str) -> HttpResponse {
+        let (status, message) = match self {
+            ApiError::BadRequest(msg) => (400, msg),
+            ApiError::NotFound(msg) => (404, msg),
+            ApiError::MethodNotAllowed => (405, "Method not allowed".into()),
+            ApiError::UnsupportedMediaType => (415, "Content-Type must be application/json".into()),
+            ApiError::Volume(err) => {
+                let status = match err {
+                    VolumeControllerError::DeviceNotFound(_)
+                    | VolumeControllerError::ApplicationNotFound(_) => 404,
+                    VolumeControllerError::InvalidVolumePercentage(_) => 400,
+                    VolumeControllerError::Unavailable(_) => 503,
+                    _ => 500,
+                };
+                (status, err.to_string())
+            }
+            ApiError::Unavailable(msg) => (503, msg),
+        };
+
+        HttpResponse::json(status, &json!({ "type": name, "error": message }))
+    }
+}
+
+impl From<VolumeControllerError> for ApiError {
+    fn from(err: VolumeControllerError) -> Self {
+        ApiError::Volume(err)
+    }
+}
+
+#[derive(Deserialize)]
+struct VolumeBody {
+    volume: VolumePercent,
+}
+
+#[derive(Deserialize)]
+struct MuteBody {
+    mute: bool,
+}
+
+type ApiResult = Result<HttpResponse, ApiError>;
+
+async fn route(request: HttpRequest, state: &VolumeCommandSender) -> HttpResponse {
+    let segments = request.segments();
+    let segments: Vec<&str> = segments.iter().map(|s| s.as_str()).collect();
+
+    let is_get = request.method == "GET";
+    let is_put = matches!(request.method.as_str(), "PUT" | "POST");
+
+    let (name, result) = match segments.as_slice() {
+        ["api", "devices"] if is_get => ("get_playback_devices", get_devices(state).await),
+        ["api", "devices", id, "applications"] if is_get => (
+            "get_device_applications",
+            get_device_applications(state, id).await,
+        ),
+        ["api", "devices", id, "volume"] if is_get => {
+            ("device_get_volume", get_device_volume(state, id).await)
+        }
+        ["api", "devices", id, "volume"] if is_put => {
+            ("device_set_volume", set_device_volume(state, id, &request))
+        }
+        ["api", "devices", id, "mute"] if is_put => {
+            ("device_mute", set_device_mute(state, id, &request))
+        }
+        ["api", "applications"] if is_get => ("get_applications", get_applications(state).await),
+        ["api", "applications", id] if is_get => {
+            ("get_application", get_application(state, id).await)
+        }
+        ["api", "applications", id, "volume"] if is_get => (
+            "application_get_volume",
+            get_application_volume(state, id).await,
+        ),
+        ["api", "applications", id, "icon"] if is_get => (
+            "application_get_icon",
+            get_application_icon(state, id).await,
+        ),
+        ["api", "applications", id, "volume"] if is_put => (
+            "application_set_volume",
+            set_application_volume(state, id, &request),
+        ),
+        ["api", "applications", id, "mute"] if is_put => (
+            "application_mute",
+            set_application_mute(state, id, &request),
+        ),
+        ["api", "devices", ..] | ["api", "applications", ..] => {
+            ("request", Err(ApiError::MethodNotAllowed))
+        }
+        _ => ("request", Err(ApiError::NotFound(request.path.clone()))),
+    };
+
+    match result {
+        Ok(response) => response,
+        Err(err) => err.into_response(name),
+    }
+}
+
+// ============================ Helpers ============================
+/// Fire and forget commands answer the same way as over WebSocket.
+fn accepted(state: &VolumeCommandSender, command: VolumeCommand) -> ApiResult {
+    let name = command.get_name();
+    state.send(command).map_err(ApiError::Unavailable)?;
+    Ok(HttpResponse::json(202, &data(&name, &"REQUEST ACCEPTED")))
+}
+
+fn data<T: serde::Serialize>(name: &str, value: &T) -> serde_json::Value {
+    json!({ "type": name, "data": value })
+}
+
+/// Other content types are refused, a page can send them cross-origin without a preflight.
+fn parse_body<'a, T: Deserialize<'a>>(request: &'a HttpRequest) -> Result<T, ApiError> {
+    let is_json = request
+        .header("content-type")
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"));
+    if !is_json {
+        return Err(ApiError::UnsupportedMediaType);
+    }
+
+    serde_json::from_slice(&request.body).map_err(|e| ApiError::BadRequest(e.to_string()))
+}
+
+fn parse_app_id(id: &str) -> Result<AppIdentifier, ApiError> {
+    id.parse::<AppIdentifier>()
+        .map_err(|_| ApiError::BadRequest(format!("Invalid application id: {}", id)))
+}
+
+// ============================ Devices ============================
+async fn get_devices(state: &VolumeCommandSender) -> ApiResult {
+    let devices = request(state, |sender| VolumeCommand::GetPlaybackDevices {
+        request_id: String::new(),
+        sender,
+    })
+    .await?;
+
+    Ok(HttpResponse::json(
+        200,
+        &data("get_playback_devices", &devices),
+    ))
+}
+
+async fn get_device_applications(state: &VolumeCommandSender, id: &str) -> ApiResult {
+    let applications = request(state, |sender| VolumeCommand::GetDeviceApplications {
+        request_id: String::new(),
+        id: id.to_string(),
+        sender,
+    })
+    .await?;
+
+    Ok(HttpResponse::json(
+        200,
+        &data("get_device_applications", &applications),
+    ))
+}
+
+async fn get_device_volume(state: &VolumeCommandSender, id: &str) -> ApiResult {
+    let volume = request(state, |sender| VolumeCommand::DeviceGetVolume {
+        request_id: String::new(),
+        id: id.to_string(),
+        sender,
+    })
+    .await?;
+
+    Ok(HttpResponse::json(200, &data("device_get_volume", &volume)))
+}
+
+fn set_device_volume(state: &VolumeCommandSender, id: &str, request: &HttpRequest) -> ApiResult {
+    let body: VolumeBody = parse_body(request)?;
+
+    let command = VolumeCommand::DeviceSetVolume {
+        request_id: String::new(),
+        id: id.to_string(),
+        volume: body.volume,
+    };
+    accepted(state, command)
+}
+
+fn set_device_mute(state: &VolumeCommandSender, id: &str, request: &HttpRequest) -> ApiResult {
+    let body: MuteBody = parse_body(request)?;
+
+    let request_id = String::new();
+    let id = id.to_string();
+    let command = match body.mute {
+        true => VolumeCommand::DeviceMute { request_id, id },
+        false => VolumeCommand::DeviceUnmute { request_id, id },
+    };
+    accepted(state, command)
+}
+
+// ========================= Applications ==========================
+/// Applications of every playback device.
+async fn get_applications(state: &VolumeCommandSender) -> ApiResult {
+    let devices = request(state, |sender| VolumeCommand::GetPlaybackDevices {
+        request_id: String::new(),
+        sender,
+    })
+    .await?;
+    let applications = running_apps(state, &devices).await;
+
+    Ok(HttpResponse::json(
+        200,
+        &data("get_applications", &applications),
+    ))
+}
+
+async fn get_application(state: &VolumeCommandSender, id: &str) -> ApiResult {
+    let id = parse_app_id(id)?;
+    let application = request(state, |sender| VolumeCommand::GetApplication {
+        request_id: String::new(),
+        id,
+        sender,
+    })
+    .await?;
+
+    Ok(HttpResponse::json(
+        200,
+        &data("get_application", &application),
+    ))
+}
+
+async fn get_application_volume(state: &VolumeCommandSender, id: &str) -> ApiResult {
+    let id = parse_app_id(id)?;
+    let volume = request(state, |sender| VolumeCommand::ApplicationGetVolume {
+        request_id: String::new(),
+        id,
+        sender,
+    })
+    .await?;
+
+    Ok(HttpResponse::json(
+        200,
+        &data("application_get_volume", &volume),
+    ))
+}
+
+async fn get_application_icon(state: &VolumeCommandSender, id: &str) -> ApiResult {
+    let id = parse_app_id(id)?;
+    let icon = request(state, |sender| VolumeCommand::ApplicationGetIcon {
+        request_id: String::new(),
+        id,
+        sender,
+    })
+    .await?;
+
+    Ok(HttpResponse::bytes(200, "image/webp", icon))
+}
+
+fn set_application_volume(
+    state: &VolumeCommandSender,
+    id: &str,
+    request: &HttpRequest,
+) -> ApiResult {
+    let id = parse_app_id(id)?;
+    let body: VolumeBody = parse_body(request)?;
+
+    let command = VolumeCommand::ApplicationSetVolume {
+        request_id: String::new(),
+        id,
+        volume: body.volume,
+    };
+    accepted(state, command)
+}
+
+fn set_application_mute(state: &VolumeCommandSender, id: &str, request: &HttpRequest) -> ApiResult {
+    let id = parse_app_id(id)?;
+    let body: MuteBody = parse_body(request)?;
+
+    let request_id = String::new();
+    let command = match body.mute {
+        true => VolumeCommand::ApplicationMute { request_id, id },
+        false => VolumeCommand::ApplicationUnmute { request_id, id },
+    };
+    accepted(state, command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::shared::Identifier;
+
+    fn request(method: &str, path: &str, content_type: Option<&str>) -> HttpRequest {
+        HttpRequest {
+            method: method.into(),
+            path: path.into(),
+            query: None,
+            headers: content_type
+                .map(|value| ("content-type".to_string(), value.to_string()))
+                .into_iter()
+                .collect(),
+            body: br#"{"volume": 0.5, "mute": true}"#.to_vec(),
+        }
+    }
+
+    async fn status(request: HttpRequest) -> u16 {
+        route(request, &VolumeCommandSender::new()).await.status
+    }
+
+    #[tokio::test]
+    async fn refuses_bodies_that_are_not_json() {
+        let path = "/api/devices/a/volume";
+        assert_eq!(status(request("PUT", path, None)).await, 415);
+        assert_eq!(status(request("PUT", path, Some("text/plain"))).await, 415);
+        assert_eq!(
+            status(request(
+                "POST",
+                "/api/applications/12/mute",
+                Some("application/x-www-form-urlencoded")
+            ))
+            .await,
+            415
+        );
+    }
+
+    #[tokio::test]
+    async fn accepts_json_with_parameters() {
+        // Nothing runs the volume thread here, so a valid request can't be sent on.
+        let json = Some("Application/JSON; charset=utf-8");
+        assert_eq!(
+            status(request("PUT", "/api/devices/a/volume", json)).await,
+            503
+        );
+        assert_eq!(status(request("GET", "/api/devices", None)).await, 503);
+    }
+
+    #[tokio::test]
+    async fn answers_unknown_routes() {
+        let json = Some("application/json");
+        assert_eq!(status(request("GET", "/api/nothing", None)).await, 404);
+        assert_eq!(status(request("OPTIONS", "/api/devices", None)).await, 405);
+        assert_eq!(
+            status(request("GET", "/api/devices/a/mute", None)).await,
+            405
+        );
+        assert_eq!(
+            status(request("PUT", "/api/applications/x/volume", json)).await,
+            400
+        );
+    }
+
+    #[test]
+    fn event_filters_must_parse() {
+        let mut events = request("GET", "/api/events", None);
+        events.query = Some("id=app:12&id=device:a%3Ab".into());
+        let filters = sse::parse_filters(&events).unwrap();
+        assert_eq!(
+            filters,
+            vec![Identifier::App(12), Identifier::Device("a:b".into())]
+        );
+
+        events.query = Some("id=app:abc".into());
+        assert!(sse::parse_filters(&events).is_err());
+        events.query = Some("id=speakers".into());
+        assert!(sse::parse_filters(&events).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+use std::{thread, time::Duration};
+
+use chrono::{Local, Timelike};
+use uuid::Uuid;
+
+use shared_types::AudioApplication;
+
+use crate::types::{
+    rules::{ActionTarget, Condition, Rule, Trigger},
+    shared::{ChangeType, EntityState, Identifier, UpdateChange},
+    storage::Settings,
+};
+
+use super::{
+    runtime as rt,
+    settings::update_settings,
+    volume_control::{playback_devices, running_apps, AppCache},
+    ServerContext,
+};
+
+/// How often `spawn_rule_clock` looks at the time, well below a minute.
+const CLOCK_INTERVAL: Duration = Duration::from_secs(15);
+
+// ============================ Evaluation ============================
+/// Runs the rules on the `UpdateChange` stream, called from the update thread.
+/// - Queries the volume thread, blocking until it answers.
+/// - An expired app is matched by what `apps` knew about it.
+pub fn handle_update(context: &ServerContext, msg: &UpdateChange, apps: &mut AppCache) {
+    let state = match &msg.change {
+        ChangeType::StateChange { state } => state,
+        _ => return,
+    };
+
+    let trigger = match (&msg.id, state) {
+        (Identifier::App(_), EntityState::Created) => Trigger::AppCreated,
+        (Identifier::App(_), EntityState::Disconnect) => Trigger::AppExpired,
+        (Identifier::App(_), EntityState::Active) => Trigger::AppActive,
+        (Identifier::Device(_), EntityState::Created) => Trigger::DeviceAdded,
+        (Identifier::Device(_), EntityState::Default) => Trigger::DefaultDeviceChanged,
+        _ => return,
+    };
+
+    let (rules, tracks_apps) = context.storage.inspect(|settings| {
+        let tracks_apps = settings
+            .rules
+            .iter()
+            .any(|rule| rule.enabled && rule.trigger.has_app());
+        (matching_rules(settings, trigger), tracks_apps)
+    });
+
+    // Looked up while the app is around, `AppExpired` rules match on it later.
+    let app = match msg.id {
+        Identifier::App(pid) if trigger == Trigger::AppExpired => apps.cached(pid),
+        Identifier::App(pid) if tracks_apps => apps.get(context, pid),
+        Identifier::App(_) | Identifier::Device(_) => None,
+    };
+    if rules.is_empty() {
+        return;
+    }
+
+    rt::block_on(async {
+        let mut running = None;
+        for rule in &rules {
+            let holds = match app {
+                Some(app) => rule
+                    .conditions
+                    .iter()
+                    .all(|c| c.matches(&app.name, app.path.as_deref())),
+                None if trigger.has_app() => rule.conditions.is_empty(),
+                None => any_running(context, &mut running, &rule.conditions).await,
+            };
+
+            if holds {
+                run_rule(context, rule, Some(&msg.id), &mut running).await;
+            }
+        }
+    });
+}
+
+fn matching_rules(settings: &Settings, trigger: Trigger) -> Vec<Rule> {
+    settings
+        .rules
+        .iter()
+        .filter(|rule| rule.enabled && rule.trigger == trigger)
+        .cloned()
+        .collect()
+}
+
+/// Every condition is met by some running app.
+async fn any_running(
+    context: &ServerContext,
+    running: &mut Option<Vec<AudioApplication>>,
+    conditions: &[Condition],
+) -> bool {
+    if conditions.is_empty() {
+        return true;
+    }
+
+    let apps = running_once(context, running).await;
+    conditions.iter().all(|condition| {
+        apps.iter()
+            .any(|app| condition.matches(&app.process.name, app.process.path.as_deref()))
+    })
+}
+
+/// Lists the running apps the first time they are needed for an event.
+async fn running_once<'a>(
+    context: &ServerContext,
+    running: &'a mut Option<Vec<AudioApplication>>,
+) -> &'a [AudioApplication] {
+    if running.is_none() {
+        let devices = playback_devices(&context.volume).await.unwrap_or_default();
+        *running = Some(running_apps(&context.volume, &devices).await);
+    }
+    running.as_deref().unwrap_or_default()
+}
+
+/// Sends every action of the rule, `trigger` is the app or device that fired it.
+async fn run_rule(
+    context: &ServerContext,
+    rule: &Rule,
+    trigger: Option<&Identifier>,
+    running: &mut Option<Vec<AudioApplication>>,
+) {
+    println!("[rules] Running \"{}\"", rule.name);
+
+    for action in &rule.actions {
+        let targets = match &action.target {
+            ActionTarget::Command => vec![None],
+            ActionTarget::Trigger => vec![trigger.cloned()],
+            ActionTarget::Matching { condition } => {
+                let apps = running_once(context, running).await;
+                apps.iter()
+                    .filter(|app| condition.matches(&app.process.name, app.process.path.as_deref()))
+                    .map(|app| Some(Identifier::App(app.process.id)))
+                    .collect()
+            }
+        };
+
+        for target in targets {
+            let mut command = action.command.clone();
+            if let Some(target) = &target {
+                if !command.retarget(target) {
+                    continue;
+                }
+            }
+
+            if let Err(e) = context.volume.send(command) {
+                eprintln!("[rules] \"{}\": {}", rule.name, e);
+            }
+        }
+    }
+}
+
+/// Fires `TimeOfDay` rules once at the start of their minute.
+pub fn spawn_rule_clock(context: &ServerContext) {
+    let context = context.clone();
+
+    thread::spawn(move || {
+        let mut last_minute = None;
+
+        loop {
+            let now = Local::now();
+            let minute = (now.hour(), now.minute());
+
+            if last_minute.is_some_and(|last| last != minute) {
+                let trigger = Trigger::TimeOfDay {
+                    hour: minute.0,
+                    minute: minute.1,
+                };
+
+                let rules = context
+                    .storage
+                    .inspect(|settings| matching_rules(settings, trigger));
+                rt::block_on(async {
+                    let mut running = None;
+                    for rule in &rules {
+                        if any_running(&context, &mut running, &rule.conditions).await {
+                            run_rule(&context, rule, None, &mut running).await;
+                        }
+                    }
+                });
+            }
+            last_minute = Some(minute);
+
+            thread::sleep(CLOCK_INTERVAL);
+        }
+    });
+}
+
+// ============================ Management ============================
+/// Adds the rule, or replaces the one with the same `id`.
+/// - A rule without an `id` gets a new one.
+/// - Saved with the settings, see `update_settings`.
+pub fn save_rule(context: &ServerContext, mut rule: Rule) -> Result<Rule, String> {
+    if rule.id.is_empty() {
+        rule.id = Uuid::new_v4().to_string();
+    }
+
+    let mut settings = context.storage.get();
+    match settings.rules.iter_mut().find(|saved| saved.id == rule.id) {
+        Some(saved) => *saved = rule.clone(),
+        None => settings.rules.push(rule.clone()),
+    }
+
+    update_settings(context, settings)?;
+    Ok(rule)
+}
+
+pub fn delete_rule(context: &ServerContext, id: &str) -> Result<(), String> {
+    let mut settings = context.storage.get();
+    let count = settings.rules.len();
+    settings.rules.retain(|rule| rule.id != id);
+
+    if settings.rules.len() == count {
+        return Err(format!("No rule with id {}", id));
+    }
+
+    update_settings(context, settings).map(|_| ())
+}
@@ -0,0 +1,34 @@
+//! Async runtime the servers run on.
+//! - With the `app` feature it is Tauri's, shared with the commands.
+//! - Without it, a multi-threaded tokio runtime started on first use.
+#[cfg(feature = "app")]
+pub use tauri::async_runtime::{block_on, spawn, JoinHandle, Mutex};
+
+#[cfg(not(feature = "app"))]
+pub use headless::{block_on, spawn, JoinHandle, Mutex};
+
+#[cfg(not(feature = "app"))]
+mod headless {
+    use std::{future::Future, sync::OnceLock};
+
+    use tokio::runtime::Runtime;
+    pub use tokio::{sync::Mutex, task::JoinHandle};
+
+    fn runtime() -> &'static Runtime {
+        static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+        RUNTIME.get_or_init(|| Runtime::new().expect("Failed to start the async runtime"))
+    }
+
+    pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        runtime().spawn(future)
+    }
+
+    /// Not to be called from the async runtime.
+    pub fn block_on<F: Future>(future: F) -> F::Output {
+        runtime().block_on(future)
+    }
+}
@@ -1,11 +1,20 @@
-use std::time::Duration;
+use std::{
+    error::Error,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
 
 use futures_util::future::{select, Either};
 use mdns_sd::{ServiceDaemon, ServiceEvent};
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
 
 use super::ServiceDiscovery;
 
-pub async fn mdns_discover(timeout: Duration) -> Result<String, Box<dyn std::error::Error>> {
+/// First server resolved over mDNS, with what its TXT record says about it.
+pub async fn mdns_discover(
+    timeout: Duration,
+) -> Result<DiscoveredServer, Box<dyn std::error::Error>> {
     let mdns = ServiceDaemon::new()?;
     let receiver = mdns.browse(&ServiceDiscovery::MDNS_DOMAIN)?;
 
@@ -16,9 +25,12 @@ pub async fn mdns_discover(timeout: Duration) -> Result<String, Box<dyn std::err
             Either::Left(_) => return Err("mDNS timeout".into()),
             Either::Right((Ok(service), _)) => {
                 if let ServiceEvent::ServiceResolved(info) = &service {
-                    if let Some(addr) = info.get_addresses().iter().next() {
-                        let port = info.get_port();
-                        return Ok(format!("{}:{}", addr, port));
+                    let addresses = endpoints(info.get_addresses(), info.get_port());
+
+                    if !addresses.is_empty() {
+                        return Ok(DiscoveredServer::from_txt(addresses, |key| {
+                            info.get_property_val_str(key).map(String::from)
+                        }));
                     }
                 }
             }
@@ -27,44 +39,22 @@ pub async fn mdns_discover(timeout: Duration) -> Result<String, Box<dyn std::err
     }
 }
 
+/// First server answering a UDP probe.
 pub async fn broadcast_discover(timeout: Duration) -> Result<String, Box<dyn std::error::Error>> {
-    let socket = tokio::net::UdpSocket::bind("0.0.0.0:0").await?;
-    socket.set_broadcast(true)?;
-
-    socket
-        .send_to(
-            ServiceDiscovery::DISCOVERY_MSG.as_bytes(),
-            ServiceDiscovery::BROADCAST_ADDRESS,
-        )
-        .await?;
+    let servers = udp_browse(timeout, true).await?;
 
-    let mut buf = [0u8; 32];
-
-    let deadline = tokio::time::sleep(timeout);
-    let accept = socket.recv_from(&mut buf);
-
-    let (len, addr) = match select(Box::pin(deadline), Box::pin(accept)).await {
-        Either::Left(_) => return Err("Broadcast timeout".into()),
-        Either::Right((result, _)) => result?,
-    };
-
-    let response = String::from_utf8_lossy(&buf[..len]);
-    // Parse "SERVER:8080" format
-    let port = response
-        .split(":")
-        .nth(1)
-        .ok_or("Invalid server response format")?
-        .parse::<u32>()
-        .unwrap_or(0);
-
-    Ok(format!("{}:{}", addr.ip(), port))
+    match servers.first() {
+        Some(server) => Ok(server.addresses[0].clone()),
+        None => Err("Broadcast timeout".into()),
+    }
 }
 
 pub async fn discover_server() -> Result<String, Box<dyn std::error::Error>> {
-    println!("[discover_server]: Trying mDNS discovery...");
+    eprintln!("[discover_server]: Trying mDNS discovery...");
     match mdns_discover(Duration::from_secs(3)).await {
-        Ok(addr) => {
-            println!("Found via mDNS: {}", addr);
+        Ok(server) => {
+            let addr = server.addresses[0].clone();
+            eprintln!("Found via mDNS: {} ({:?})", addr, server.name);
             return Ok(addr);
         }
         Err(err) => {
@@ -72,10 +62,10 @@ pub async fn discover_server() -> Result<String, Box<dyn std::error::Error>> {
         }
     };
 
-    println!("[discover_server]: Trying UDP broadcast...");
+    eprintln!("[discover_server]: Trying UDP broadcast...");
     match broadcast_discover(Duration::from_secs(3)).await {
         Ok(addr) => {
-            println!("[discover_server]: Found via broadcast: {}", addr);
+            eprintln!("[discover_server]: Found via broadcast: {}", addr);
             return Ok(addr);
         }
         Err(err) => {
@@ -85,3 +75,352 @@ pub async fn discover_server() -> Result<String, Box<dyn std::error::Error>> {
 
     Err("[discover_server]: Could not discover server. Please enter IP manually.".into())
 }
+
+// ========================= Every server ==========================
+/// A server found on the network, replies from both discovery methods are merged.
+/// - Legacy UDP replies only carry the address, the rest comes from mDNS TXT
+///   records or JSON announcements.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct DiscoveredServer {
+    /// Missing for servers that don't report one yet.
+    pub id: Option<String>,
+    pub name: Option<String>,
+    /// `ip:port` pairs the server was found on.
+    pub addresses: Vec<String>,
+    /// Application version.
+    pub version: Option<String>,
+    pub protocol_version: Option<u32>,
+    pub os: Option<String>,
+    pub tls: bool,
+    pub auth: bool,
+    /// SHA-256 fingerprint of the TLS certificate.
+    pub fingerprint: Option<String>,
+}
+
+impl DiscoveredServer {
+    /// Reads the TXT record published by `service_register`.
+    fn from_txt(addresses: Vec<String>, txt: impl Fn(&str) -> Option<String>) -> Self {
+        Self {
+            id: txt("id"),
+            name: txt("name"),
+            addresses,
+            version: txt("version"),
+            protocol_version: txt("protocol").and_then(|value| value.parse().ok()),
+            os: txt("os"),
+            tls: txt("tls").as_deref() == Some("1"),
+            auth: txt("auth").as_deref() == Some("1"),
+            fingerprint: txt("fp"),
+        }
+    }
+}
+
+/// Browses mDNS and UDP at the same time for `window`.
+/// - Servers with the same id are merged, servers without one by shared address.
+pub async fn discover_servers(window: Duration) -> Vec<DiscoveredServer> {
+    let (mdns, broadcast) = tokio::join!(mdns_browse(window), udp_browse(window, false));
+
+    let mut servers = vec![];
+    for found in [mdns, broadcast] {
+        match found {
+            Ok(found) => found
+                .into_iter()
+                .for_each(|server| merge_server(&mut servers, server)),
+            Err(e) => eprintln!("[discover_servers]: {}", e),
+        }
+    }
+    servers
+}
+
+/// `ip:port` for each resolved address, IPv4 first so `addresses[0]` is the likeliest to work.
+/// - IPv6 addresses are bracketed, their `%scope` suffix is left out.
+fn endpoints<T: ToString>(addresses: impl IntoIterator<Item = T>, port: u16) -> Vec<String> {
+    let mut endpoints: Vec<SocketAddr> = addresses
+        .into_iter()
+        .filter_map(|addr| {
+            let addr = addr.to_string();
+            let ip = addr.split('%').next()?.parse::<IpAddr>().ok()?;
+            Some(SocketAddr::new(ip, port))
+        })
+        .collect();
+
+    endpoints.sort_by_key(SocketAddr::is_ipv6);
+    endpoints.iter().map(SocketAddr::to_string).collect()
+}
+
+fn merge_server(servers: &mut Vec<DiscoveredServer>, found: DiscoveredServer) {
+    let existing = servers
+        .iter_mut()
+        .find(|server| match (&server.id, &found.id) {
+            (Some(id), Some(found_id)) => id == found_id,
+            _ => server
+                .addresses
+                .iter()
+                .any(|addr| found.addresses.contains(addr)),
+        });
+
+    let server = match existing {
+        Some(server) => server,
+        None => return servers.push(found),
+    };
+
+    server.id = server.id.take().or(found.id);
+    server.name = server.name.take().or(found.name);
+    server.version = server.version.take().or(found.version);
+    server.protocol_version = server.protocol_version.or(found.protocol_version);
+    server.os = server.os.take().or(found.os);
+    server.tls |= found.tls;
+    server.auth |= found.auth;
+    server.fingerprint = server.fingerprint.take().or(found.fingerprint);
+    for address in found.addresses {
+        if !server.addresses.contains(&address) {
+            server.addresses.push(address);
+        }
+    }
+}
+
+async fn mdns_browse(window: Duration) -> Result<Vec<DiscoveredServer>, Box<dyn Error>> {
+    let mdns = ServiceDaemon::new()?;
+    let receiver = mdns.browse(ServiceDiscovery::MDNS_DOMAIN)?;
+
+    let mut servers = vec![];
+    let deadline = tokio::time::sleep(window);
+    tokio::pin!(deadline);
+
+    loop {
+        tokio::select! {
+            _ = &mut deadline => break,
+            event = receiver.recv_async() => match event {
+                Ok(ServiceEvent::ServiceResolved(info)) => {
+                    let addresses = endpoints(info.get_addresses(), info.get_port());
+
+                    servers.push(DiscoveredServer::from_txt(addresses, |key| {
+                        info.get_property_val_str(key).map(String::from)
+                    }));
+                }
+                Ok(_) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    let _ = mdns.shutdown();
+    Ok(servers)
+}
+
+// ======================= Discovery protocol =======================
+/// Bumped when a field changes meaning, new optional fields don't need it.
+pub const DISCOVERY_VERSION: u32 = 1;
+
+/// JSON datagrams on `ServiceDiscovery::LISTEN_PORT`.
+/// - Servers still answer the plain `DISCOVER_VOLUMIZE` probe with `SERVER:<port>`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum DiscoveryMessage {
+    #[serde(rename = "volumize.discover")]
+    Probe { version: u32 },
+    #[serde(rename = "volumize.server")]
+    Announce {
+        version: u32,
+        id: String,
+        name: String,
+        port: u16,
+        tls: bool,
+        protocol_version: u32,
+    },
+}
+
+/// Probes over IPv4 broadcast and IPv6 multicast, in both the JSON and legacy format.
+/// - Returns once `window` ends, or at the first reply when `first` is set.
+/// - A family that fails is logged and left out, the servers found so far are kept.
+async fn udp_browse(
+    window: Duration,
+    first: bool,
+) -> Result<Vec<DiscoveredServer>, Box<dyn Error>> {
+    let mut v4 = probe_socket("0.0.0.0:0", ServiceDiscovery::BROADCAST_ADDRESS.into()).await;
+    let mut v6 = probe_socket("[::]:0", ServiceDiscovery::MULTICAST_ADDRESS.into()).await;
+    if v4.is_none() && v6.is_none() {
+        return Err("No discovery probe could be sent".into());
+    }
+
+    let mut servers = vec![];
+    let deadline = tokio::time::sleep(window);
+    tokio::pin!(deadline);
+
+    while v4.is_some() || v6.is_some() {
+        let (reply, addr) = tokio::select! {
+            _ = &mut deadline => break,
+            received = recv_reply(v4.as_ref()) => match received {
+                Ok(received) => received,
+                Err(e) => {
+                    eprintln!("[udp_browse]: IPv4 receive failed: {}", e);
+                    v4 = None;
+                    continue;
+                }
+            },
+            received = recv_reply(v6.as_ref()) => match received {
+                Ok(received) => received,
+                Err(e) => {
+                    eprintln!("[udp_browse]: IPv6 receive failed: {}", e);
+                    v6 = None;
+                    continue;
+                }
+            },
+        };
+
+        match parse_reply(&reply, addr.ip()) {
+            Ok(server) => servers.push(server),
+            Err(e) => eprintln!("[udp_browse]: {}", e),
+        }
+
+        if first && !servers.is_empty() {
+            break;
+        }
+    }
+
+    Ok(servers)
+}
+
+/// `None` when the socket can't be bound or the probes can't be sent, for example on
+/// hosts without IPv4 or IPv6.
+async fn probe_socket(bind: &str, target: SocketAddr) -> Option<UdpSocket> {
+    let socket = UdpSocket::bind(bind).await.ok()?;
+    if target.is_ipv4() {
+        socket.set_broadcast(true).ok()?;
+    }
+
+    match send_probes(&socket, target).await {
+        Ok(()) => Some(socket),
+        Err(e) => {
+            eprintln!("[udp_browse]: Probe to {} failed: {}", target, e);
+            None
+        }
+    }
+}
+
+/// Current servers answer both probes, the replies are merged by address.
+async fn send_probes(socket: &UdpSocket, target: SocketAddr) -> std::io::Result<()> {
+    let probe = DiscoveryMessage::Probe {
+        version: DISCOVERY_VERSION,
+    };
+    let probe = serde_json::to_vec(&probe)?;
+
+    socket.send_to(&probe, target).await?;
+    socket
+        .send_to(ServiceDiscovery::DISCOVERY_MSG.as_bytes(), target)
+        .await?;
+    Ok(())
+}
+
+/// Never resolves without a socket.
+async fn recv_reply(socket: Option<&UdpSocket>) -> std::io::Result<(Vec<u8>, SocketAddr)> {
+    let socket = match socket {
+        Some(socket) => socket,
+        None => return std::future::pending().await,
+    };
+
+    let mut buf = [0u8; 1024];
+    let (len, addr) = socket.recv_from(&mut buf).await?;
+    Ok((buf[..len].to_vec(), addr))
+}
+
+/// A `volumize.server` announcement, or `SERVER:<port>` from older servers.
+fn parse_reply(reply: &[u8], ip: IpAddr) -> Result<DiscoveredServer, String> {
+    if let Ok(message) = serde_json::from_slice::<DiscoveryMessage>(reply) {
+        return match message {
+            DiscoveryMessage::Announce {
+                id,
+                name,
+                port,
+                tls,
+                protocol_version,
+                ..
+            } => Ok(DiscoveredServer {
+                id: Some(id),
+                name: Some(name),
+                addresses: vec![SocketAddr::new(ip, port).to_string()],
+                protocol_version: Some(protocol_version),
+                tls,
+                ..Default::default()
+            }),
+            DiscoveryMessage::Probe { .. } => Err("Ignoring probe from another client".into()),
+        };
+    }
+
+    let reply = String::from_utf8_lossy(reply);
+    let port = reply
+        .strip_prefix("SERVER:")
+        .and_then(|port| port.trim().parse::<u16>().ok())
+        .ok_or_else(|| format!("Invalid server reply: {}", reply))?;
+
+    Ok(DiscoveredServer {
+        addresses: vec![SocketAddr::new(ip, port).to_string()],
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20));
+
+    #[test]
+    fn parses_announcements() {
+        let announce = DiscoveryMessage::Announce {
+            version: DISCOVERY_VERSION,
+            id: "server-id".into(),
+            name: "Desk".into(),
+            port: 9002,
+            tls: true,
+            protocol_version: 3,
+        };
+        let reply = serde_json::to_vec(&announce).unwrap();
+
+        let server = parse_reply(&reply, IP).unwrap();
+        assert_eq!(server.id.as_deref(), Some("server-id"));
+        assert_eq!(server.name.as_deref(), Some("Desk"));
+        assert_eq!(server.addresses, vec!["192.168.1.20:9002".to_string()]);
+        assert_eq!(server.protocol_version, Some(3));
+        assert!(server.tls);
+    }
+
+    #[test]
+    fn parses_legacy_replies() {
+        let server = parse_reply(b"SERVER:9003\n", IP).unwrap();
+        assert_eq!(server.addresses, vec!["192.168.1.20:9003".to_string()]);
+        assert_eq!(server.id, None);
+
+        let server = parse_reply(b"SERVER:9003", IpAddr::V6(Ipv6Addr::LOCALHOST)).unwrap();
+        assert_eq!(server.addresses, vec!["[::1]:9003".to_string()]);
+    }
+
+    #[test]
+    fn formats_endpoints_ipv4_first() {
+        let addresses = ["fe80::1%2", "192.168.1.20", "::1", "not an ip"];
+
+        let endpoints = endpoints(addresses, 9002);
+        assert_eq!(
+            endpoints,
+            vec![
+                "192.168.1.20:9002".to_string(),
+                "[fe80::1]:9002".to_string(),
+                "[::1]:9002".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_probes_and_garbage() {
+        let probe = DiscoveryMessage::Probe {
+            version: DISCOVERY_VERSION,
+        };
+        let probe = serde_json::to_vec(&probe).unwrap();
+
+        assert!(parse_reply(&probe, IP).is_err());
+        assert!(parse_reply(ServiceDiscovery::DISCOVERY_MSG.as_bytes(), IP).is_err());
+        assert!(parse_reply(b"SERVER:high", IP).is_err());
+        assert!(parse_reply(b"SERVER:70000", IP).is_err());
+    }
+}
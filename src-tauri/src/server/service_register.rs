@@ -1,45 +1,157 @@
-use futures_util::future::{select, Either};
+use futures_util::future::{select, try_join_all, Either};
 use std::{
-    net::SocketAddr,
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
     sync::{Arc, Mutex},
     time::Duration,
 };
-use tauri::{
-    async_runtime::{self as rt},
-    AppHandle, Manager,
-};
 use tokio::net::UdpSocket;
 use tokio_util::sync::CancellationToken;
 
-use super::{super::types::tray::Discovery, RunningServer, ServiceDiscovery};
+use super::{
+    super::types::{storage::Settings, tray::Discovery},
+    bind, runtime as rt,
+    service_discovery::{DiscoveryMessage, DISCOVERY_VERSION},
+    unix_millis, DiscoveryStatus, RunningServer, ServerContext, ServiceDiscovery, PROTOCOL_VERSION,
+};
+
+/// How often `watch_addresses` compares the interface addresses.
+const ADDRESS_POLL_INTERVAL: Duration = Duration::from_secs(10);
 
-pub fn start_service_register(port: u16, app_handle: &AppHandle, policy: Discovery) {
-    let state = app_handle.state::<ServiceDiscovery>();
+/// How this server presents itself to clients looking for it.
+struct Identity {
+    id: String,
+    name: String,
+}
+
+impl Identity {
+    fn new(settings: &Settings) -> Self {
+        Self {
+            id: settings.server_id.clone(),
+            name: settings.display_name(),
+        }
+    }
 
+    /// Read back by `DiscoveredServer::from_txt`.
+    /// - There is no TLS or authentication yet, `fp` is only published with a certificate.
+    fn txt_properties(&self) -> HashMap<String, String> {
+        let properties = [
+            ("id", self.id.clone()),
+            ("name", self.name.clone()),
+            ("version", env!("CARGO_PKG_VERSION").to_string()),
+            ("protocol", PROTOCOL_VERSION.to_string()),
+            ("os", std::env::consts::OS.to_string()),
+            ("tls", "0".to_string()),
+            ("auth", "0".to_string()),
+        ];
+
+        properties
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect()
+    }
+
+    /// Replies to both probe formats, parsed by `service_discovery::parse_reply`.
+    fn probe_replies(&self, port: u16) -> ProbeReplies {
+        let announce = DiscoveryMessage::Announce {
+            version: DISCOVERY_VERSION,
+            id: self.id.clone(),
+            name: self.name.clone(),
+            port,
+            tls: false,
+            protocol_version: PROTOCOL_VERSION,
+        };
+
+        ProbeReplies {
+            json: serde_json::to_vec(&announce).unwrap_or_default(),
+            legacy: format!("SERVER:{}", port).into_bytes(),
+        }
+    }
+}
+
+struct ProbeReplies {
+    json: Vec<u8>,
+    legacy: Vec<u8>,
+}
+
+/// Advertises the port the WebSocket server is actually bound to.
+/// - `OnDuration` opens a new window, for when the policy is chosen.
+pub fn start_service_register(context: &ServerContext, policy: Discovery) {
+    let window = match policy {
+        Discovery::OnDuration(run_duration) => Some(run_duration),
+        _ => None,
+    };
+    register_with_status(context, policy, window);
+}
+
+/// Advertises again with the policy in effect, after the port or server name changed.
+/// - An open `OnDuration` window keeps its deadline, a closed one stays closed.
+pub fn refresh_service_register(context: &ServerContext) {
+    let status = context.discovery.status();
+    register_with_status(context, status.chosen, status.remaining());
+}
+
+/// `window` is how long an `OnDuration` policy stays on from now, `None` once it closed.
+fn register_with_status(context: &ServerContext, chosen: Discovery, window: Option<Duration>) {
+    let state = &context.discovery;
+    let closed = DiscoveryStatus {
+        chosen,
+        ..Default::default()
+    };
+
+    let policy = match (chosen, window) {
+        (Discovery::OnDuration(_), None) => Discovery::TurnOff,
+        (policy, _) => policy,
+    };
     if matches!(policy, Discovery::TurnOff) {
         // Stop and clear existing server, then return.
         replace_server_state(&state.server, None);
+        set_status(context, closed);
         return;
     }
 
+    let ws_state = &context.websocket;
+    let addresses: Vec<IpAddr> = ws_state.local_addresses().iter().map(|a| a.ip()).collect();
+    let port = match ws_state.local_port() {
+        Some(port) => port,
+        None => {
+            eprintln!("[start_service_register]: WebSocket server is not running");
+            replace_server_state(&state.server, None);
+            set_status(context, closed);
+            return;
+        }
+    };
+
+    let identity = Identity::new(&context.storage.get());
+
     let cancel = CancellationToken::new();
     let cancel_for_worker = cancel.clone();
+    let mut status = DiscoveryStatus {
+        policy,
+        expires_at: None,
+        chosen,
+    };
 
     // If there's a duration, spawn a timer task to cancel later.
-    if let Discovery::OnDuration(run_duration) = policy {
+    if let (Discovery::OnDuration(_), Some(run_duration)) = (policy, window) {
         println!(
             "[start_service_register]: Turn on register service for, {:?}",
             run_duration
         );
+        status.expires_at = Some(unix_millis() + run_duration.as_millis() as u64);
 
         let cancel_for_timer = cancel.clone();
+        let context_for_timer = context.clone();
         rt::spawn(async move {
             let deadline = tokio::time::sleep(run_duration);
             let await_cancel = cancel_for_timer.cancelled();
 
             // This is to make sure task/thread stops when recieved a cancellation.
             match select(Box::pin(deadline), Box::pin(await_cancel)).await {
-                Either::Left(_) => cancel_for_timer.cancel(),
+                Either::Left(_) => {
+                    cancel_for_timer.cancel();
+                    discovery_expired(&context_for_timer);
+                }
                 Either::Right(_) => {} // it is already cancelled, no need to cancel again.
             }
         });
@@ -49,7 +161,7 @@ pub fn start_service_register(port: u16, app_handle: &AppHandle, policy: Discove
         println!("[start_service_register]: Starting up...");
 
         // List all mDNS command: dns-sd -B _services._dns-sd._udp
-        if let Err(e) = register_service(port, cancel_for_worker).await {
+        if let Err(e) = register_service(port, &addresses, &identity, cancel_for_worker).await {
             println!("[start_service_register] Failed: {}", e);
         }
 
@@ -64,6 +176,26 @@ pub fn start_service_register(port: u16, app_handle: &AppHandle, policy: Discove
 
     // Replace old server and clear its state.
     replace_server_state(&state.server, Some(new_server));
+    set_status(context, status);
+}
+
+fn set_status(context: &ServerContext, status: DiscoveryStatus) {
+    context.discovery.set_status(status);
+    context.events.discovery_changed(&status);
+}
+
+/// Only the status goes back to off, `chosen` keeps the policy.
+/// - `refresh_service_register` leaves a closed window closed.
+fn discovery_expired(context: &ServerContext) {
+    println!("[start_service_register]: Discovery window closed");
+    let chosen = context.discovery.status().chosen;
+    set_status(
+        context,
+        DiscoveryStatus {
+            chosen,
+            ..Default::default()
+        },
+    );
 }
 
 fn replace_server_state(
@@ -89,68 +221,213 @@ fn replace_server_state(
 
 async fn register_service(
     port: u16,
+    addresses: &[IpAddr],
+    identity: &Identity,
     cancel: CancellationToken,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mdns = init_mdns_service(port)?;
+    let mdns = init_mdns_daemon(addresses)?;
+
+    let advertised = advertised_addresses(addresses);
+    let service = mdns_service_info(port, &advertised, identity)?;
+    let fullname = service.get_fullname().to_string();
+    mdns.register(service)?;
 
     println!(
-        "[register_service]: mDNS service registered on port {}",
-        port
+        "[register_service]: mDNS service {} registered on port {} for {:?}",
+        fullname, port, advertised
     );
 
-    let result = run_udp_responder(port, cancel).await;
+    let responder = run_udp_responder(port, addresses, identity, cancel.clone());
+    let watcher = watch_addresses(&mdns, port, addresses, identity, advertised, cancel);
+    let (result, _) = tokio::join!(responder, watcher);
 
     println!("[register_service]: Shutting down mDNS service...");
-    shutdown_mdns_service(&mdns)?;
+    shutdown_mdns_service(&mdns, &fullname)?;
     result
 }
 
-fn init_mdns_service(port: u16) -> Result<mdns_sd::ServiceDaemon, Box<dyn std::error::Error>> {
+/// Unspecified bind addresses send on every interface of that IP family,
+/// specific addresses restrict mDNS to those interfaces.
+fn init_mdns_daemon(
+    addresses: &[IpAddr],
+) -> Result<mdns_sd::ServiceDaemon, Box<dyn std::error::Error>> {
+    use mdns_sd::IfKind;
+
+    let mdns = mdns_sd::ServiceDaemon::new()?;
+
+    let any_v4 = addresses
+        .iter()
+        .any(|ip| ip.is_ipv4() && ip.is_unspecified());
+    let any_v6 = addresses
+        .iter()
+        .any(|ip| ip.is_ipv6() && ip.is_unspecified());
+
+    if any_v4 || any_v6 {
+        if !any_v4 {
+            mdns.disable_interface(IfKind::IPv4)?;
+        }
+        if !any_v6 {
+            mdns.disable_interface(IfKind::IPv6)?;
+        }
+    } else {
+        mdns.disable_interface(IfKind::All)?;
+        for ip in addresses {
+            mdns.enable_interface(IfKind::Addr(*ip))?;
+        }
+    }
+
+    Ok(mdns)
+}
+
+/// The instance and host are named after the server id, the display name goes in the TXT record.
+fn mdns_service_info(
+    port: u16,
+    advertised: &[IpAddr],
+    identity: &Identity,
+) -> Result<mdns_sd::ServiceInfo, Box<dyn std::error::Error>> {
+    let host_name = format!("volumize-{}.local.", identity.id);
+
     let service = mdns_sd::ServiceInfo::new(
         ServiceDiscovery::MDNS_DOMAIN,
-        ServiceDiscovery::MDNS_INSTANCE_NAME,
-        &"volumize_server.local.", // using fixed host name.
-        local_ip_address::local_ip()?,
+        &identity.id,
+        &host_name,
+        advertised,
         port,
-        None,
+        identity.txt_properties(),
     )?;
 
-    let mdns = mdns_sd::ServiceDaemon::new()?;
-    mdns.register(service)?;
+    Ok(service)
+}
 
-    Ok(mdns)
+/// Every address a client could reach the WebSocket server on.
+/// - Unspecified bind addresses expand to every interface address of that IP family.
+/// - Loopback and link-local addresses are left out.
+fn advertised_addresses(addresses: &[IpAddr]) -> Vec<IpAddr> {
+    let interfaces = local_ip_address::list_afinet_netifas().unwrap_or_default();
+    let mut advertised = vec![];
+
+    for ip in addresses {
+        if !ip.is_unspecified() {
+            advertised.push(*ip);
+            continue;
+        }
+
+        let family = interfaces
+            .iter()
+            .map(|(_, addr)| *addr)
+            .filter(|addr| addr.is_ipv4() == ip.is_ipv4() && is_advertisable(addr));
+        advertised.extend(family);
+    }
+
+    advertised.sort();
+    advertised.dedup();
+    advertised
 }
 
-fn bind_udp_socket(socket_addr: SocketAddr) -> Result<UdpSocket, std::io::Error> {
-    use socket2::{Domain, Protocol, Type};
+fn is_advertisable(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => !v4.is_loopback() && !v4.is_link_local(),
+        IpAddr::V6(v6) => !v6.is_loopback() && !bind::is_ipv6_link_local(ip),
+    }
+}
 
-    let domain = match socket_addr {
-        SocketAddr::V4(_) => Domain::IPV4,
-        SocketAddr::V6(_) => Domain::IPV6,
-    };
+/// Re-registers the mDNS service whenever the advertised addresses change.
+/// - Polled, network switches and DHCP renewals have no portable notification.
+/// - An empty set (no network) keeps the last registration until addresses come back.
+async fn watch_addresses(
+    mdns: &mdns_sd::ServiceDaemon,
+    port: u16,
+    addresses: &[IpAddr],
+    identity: &Identity,
+    mut current: Vec<IpAddr>,
+    cancel: CancellationToken,
+) {
+    let mut ticker = tokio::time::interval(ADDRESS_POLL_INTERVAL);
+    ticker.tick().await; // Skip the first immediate tick.
+
+    loop {
+        let cancellation = cancel.cancelled();
+        let tick = ticker.tick();
+
+        if let Either::Left(_) = select(Box::pin(cancellation), Box::pin(tick)).await {
+            break;
+        }
+
+        let latest = advertised_addresses(addresses);
+        if latest.is_empty() || latest == current {
+            continue;
+        }
+
+        println!("[watch_addresses]: Addresses changed to {:?}", latest);
+        let registered = mdns_service_info(port, &latest, identity)
+            .and_then(|service| Ok(mdns.register(service)?));
 
-    let socket = socket2::Socket::new(domain, Type::DGRAM, Some(Protocol::UDP))?;
-    let _ = socket.set_reuse_address(true);
-    #[cfg(unix)]
-    let _ = socket.set_reuse_port(true);
+        match registered {
+            Ok(()) => current = latest,
+            Err(e) => eprintln!("[watch_addresses] Failed to re-register: {}", e),
+        }
+    }
+}
 
-    socket.bind(&socket_addr.into())?;
-    socket.set_nonblocking(true)?; // Nonblocking for Tokio
+fn bind_udp_socket(socket_addr: SocketAddr) -> Result<UdpSocket, std::io::Error> {
+    let socket = bind::bind_socket(socket_addr, socket2::Type::DGRAM)?;
 
     // Conversion: socket2 --> std --> tokio.
     let std_udp: std::net::UdpSocket = socket.into();
     UdpSocket::from_std(std_udp)
 }
 
+/// Answers discovery probes on every bind address.
+/// - On Linux, broadcast probes only reach sockets bound to an unspecified address.
+/// - IPv6 probes are multicast, the unspecified IPv6 socket joins the group on the default interface.
 async fn run_udp_responder(
     port: u16,
+    addresses: &[IpAddr],
+    identity: &Identity,
     cancel: CancellationToken,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let socket = bind_udp_socket(ServiceDiscovery::LISTEN_ADDRESS.into())?;
-    let mut buf = [0u8; 24];
+    let mut sockets = vec![];
+    for ip in addresses {
+        let listen_addr = SocketAddr::new(*ip, ServiceDiscovery::LISTEN_PORT);
+        let socket = match bind_udp_socket(listen_addr) {
+            Ok(socket) => socket,
+            Err(e) => {
+                eprintln!("[run_udp_responder] Skipping {}: {}", listen_addr, e);
+                continue;
+            }
+        };
+
+        if ip.is_ipv6() && ip.is_unspecified() {
+            if let Err(e) = socket.join_multicast_v6(&ServiceDiscovery::MULTICAST_GROUP, 0) {
+                eprintln!("[run_udp_responder] Failed to join multicast group: {}", e);
+            }
+        }
+        sockets.push(socket);
+    }
+
+    if sockets.is_empty() {
+        return Err("No discovery socket could be bound".into());
+    }
 
     println!("Server ready (mDNS + UDP)");
 
+    let replies = identity.probe_replies(port);
+    let responders = sockets
+        .into_iter()
+        .map(|socket| respond_to_probes(socket, &replies, cancel.clone()));
+    try_join_all(responders).await?;
+
+    Ok(())
+}
+
+/// Probes of any other version are answered too, the reply carries our version.
+async fn respond_to_probes(
+    socket: UdpSocket,
+    replies: &ProbeReplies,
+    cancel: CancellationToken,
+) -> Result<(), std::io::Error> {
+    let mut buf = [0u8; 512];
+
     loop {
         let cancellation = cancel.cancelled();
         let accept = socket.recv_from(&mut buf);
@@ -160,18 +437,29 @@ async fn run_udp_responder(
             Either::Right((result, _)) => result?,
         };
 
-        if &buf[..len] == ServiceDiscovery::DISCOVERY_MSG.as_bytes() {
-            let response = format!("SERVER:{}", port);
-            socket.send_to(response.as_bytes(), addr).await?;
+        let probe = &buf[..len];
+        let reply = if probe == ServiceDiscovery::DISCOVERY_MSG.as_bytes() {
+            &replies.legacy
+        } else if let Ok(DiscoveryMessage::Probe { .. }) = serde_json::from_slice(probe) {
+            &replies.json
+        } else {
+            continue;
+        };
+
+        if let Err(e) = socket.send_to(reply, addr).await {
+            eprintln!("[respond_to_probes] Failed to reply to {}: {}", addr, e);
         }
     }
 
     Ok(())
 }
 
-fn shutdown_mdns_service(mdns: &mdns_sd::ServiceDaemon) -> Result<(), Box<dyn std::error::Error>> {
+fn shutdown_mdns_service(
+    mdns: &mdns_sd::ServiceDaemon,
+    fullname: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
     loop {
-        match mdns.unregister(ServiceDiscovery::MDNS_DOMAIN) {
+        match mdns.unregister(fullname) {
             Err(mdns_sd::Error::Again) => {
                 eprintln!("mDNS failed to shutdown, trying again...");
                 std::thread::sleep(Duration::from_millis(50));
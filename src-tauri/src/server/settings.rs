@@ -0,0 +1,123 @@
+use std::{sync::MutexGuard, thread, time::Duration};
+
+use crate::types::storage::Settings;
+
+use super::{
+    restart_websocket_server,
+    service_register::{refresh_service_register, start_service_register},
+    ServerContext,
+};
+
+/// How often `spawn_settings_watcher` looks at the settings file.
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Validates, saves and applies `new`, returns the settings now in effect.
+/// - `version` and `server_id` are kept, they can't be changed from outside.
+/// - The WebSocket server moves when the port or bind addresses change, the previous
+///   settings are put back when it can't be bound.
+/// - Heartbeat and compression apply to connections made from now on.
+/// - Blocks on the server locks, not to be called from the async runtime.
+pub fn update_settings(context: &ServerContext, new: Settings) -> Result<Settings, String> {
+    let _guard = settings_guard(context);
+    apply_settings(context, new, true)
+}
+
+/// Picks up changes made to the settings file by something else, see `update_settings`.
+/// - Returns `None` when the file matches the settings in effect.
+/// - A file that doesn't parse or validate is rejected as a whole.
+/// - The file is put back to the settings in effect when the server can't be moved,
+///   it would be applied at the next start otherwise.
+pub fn reload_settings(context: &ServerContext) -> Result<Option<Settings>, String> {
+    let _guard = settings_guard(context);
+    let mut new = context.storage.read()?;
+    let current = context.storage.get();
+    new.server_id = current.server_id.clone();
+
+    // Our own saves come back through here as well.
+    if new == current {
+        return Ok(None);
+    }
+
+    apply_settings(context, new, false).map(Some)
+}
+
+/// Checks the settings file every few seconds and reloads it when it was modified.
+/// - Rejected changes are logged, the settings in effect stay as they are.
+pub fn spawn_settings_watcher(context: &ServerContext) {
+    let context = context.clone();
+
+    thread::spawn(move || {
+        let mut modified = context.storage.modified();
+
+        loop {
+            thread::sleep(WATCH_INTERVAL);
+
+            let current = context.storage.modified();
+            if current.is_none() || current == modified {
+                continue;
+            }
+            modified = current;
+
+            match reload_settings(&context) {
+                Ok(Some(_)) => println!("Settings file changed, new settings applied"),
+                Ok(None) => {}
+                Err(e) => eprintln!("[spawn_settings_watcher] Change rejected: {}", e),
+            }
+        }
+    });
+}
+
+/// Keeps `version` and `server_id` from `current` and validates the rest.
+pub fn prepare_settings(current: &Settings, mut new: Settings) -> Result<Settings, String> {
+    new.version = current.version;
+    new.server_id = current.server_id.clone();
+    new.validate()?;
+    Ok(new)
+}
+
+fn settings_guard(context: &ServerContext) -> MutexGuard<'_, ()> {
+    match context.settings_lock.lock() {
+        Ok(guard) => guard,
+        Err(e) => e.into_inner(),
+    }
+}
+
+/// `save` is false when `new` was just read from the file.
+/// - Called with `settings_guard` held.
+fn apply_settings(context: &ServerContext, new: Settings, save: bool) -> Result<Settings, String> {
+    let current = context.storage.get();
+    let new = prepare_settings(&current, new)?;
+
+    if save {
+        context.storage.save(&new).map_err(|e| e.to_string())?;
+    }
+    context.storage.update(new.clone());
+
+    let old_port = context.websocket.local_port();
+    if let Err(e) = restart_websocket_server(&current, &new, context) {
+        rollback_settings(context, current);
+        return Err(e.to_string());
+    }
+
+    // Re-advertise, the bound port may differ from the requested one.
+    let moved = context.websocket.local_port() != old_port;
+    if new.duration != current.duration {
+        start_service_register(context, new.duration);
+    } else if moved || new.server_name != current.server_name {
+        refresh_service_register(context);
+    }
+
+    context.events.settings_changed(&new);
+    Ok(new)
+}
+
+/// Puts `previous` back after the server couldn't be moved, in the file as well.
+fn rollback_settings(context: &ServerContext, previous: Settings) {
+    if let Err(e) = context.storage.save(&previous) {
+        eprintln!(
+            "[rollback_settings] Failed to restore the settings file: {}",
+            e
+        );
+    }
+    context.storage.update(previous);
+}
@@ -0,0 +1,195 @@
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use tokio::{
+    io::AsyncWriteExt,
+    net::TcpStream,
+    sync::broadcast::{self, error::RecvError},
+    time::interval,
+};
+
+use crate::types::shared::{Identifier, UpdateChange, UPDATE_EVENT_NAME};
+
+use super::{http::HttpRequest, unix_millis, ServerContext};
+
+const LOG_CAPACITY: usize = 256;
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+const RETRY_MS: u64 = 3000;
+
+/// Recent `UpdateChange` events, numbered so SSE clients can resume.
+/// - Event ids are `<boot>-<sequence>`, a restart changes `boot` and skips replay.
+pub struct UpdateLog {
+    boot: u64,
+    sender: broadcast::Sender<(u64, UpdateChange)>,
+    recent: Mutex<(u64, VecDeque<(u64, UpdateChange)>)>,
+}
+
+impl Default for UpdateLog {
+    fn default() -> Self {
+        Self {
+            boot: unix_millis(),
+            sender: broadcast::channel(LOG_CAPACITY).0,
+            recent: Default::default(),
+        }
+    }
+}
+
+impl UpdateLog {
+    fn recent(&self) -> MutexGuard<'_, (u64, VecDeque<(u64, UpdateChange)>)> {
+        match self.recent.lock() {
+            Ok(value) => value,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    pub fn publish(&self, change: &UpdateChange) {
+        let mut recent = self.recent();
+        recent.0 += 1;
+        let sequence = recent.0;
+
+        if recent.1.len() == LOG_CAPACITY {
+            recent.1.pop_front();
+        }
+        recent.1.push_back((sequence, change.clone()));
+
+        // No receivers is not an error, nobody is listening yet.
+        let _ = self.sender.send((sequence, change.clone()));
+    }
+
+    fn event_id(&self, sequence: u64) -> String {
+        format!("{}-{}", self.boot, sequence)
+    }
+
+    /// Sequence after `last_event_id`, `None` when it belongs to another boot.
+    fn resume_from(&self, last_event_id: &str) -> Option<u64> {
+        let (boot, sequence) = last_event_id.split_once('-')?;
+        match boot.parse::<u64>().ok()? == self.boot {
+            true => sequence.parse::<u64>().ok(),
+            false => None,
+        }
+    }
+}
+
+/// `?id=app:1234&id=device:<id>` of a `GET /api/events` request.
+/// - An id that doesn't parse is an error, it would otherwise send every event.
+pub fn parse_filters(request: &HttpRequest) -> Result<Vec<Identifier>, String> {
+    request
+        .query_values("id")
+        .iter()
+        .map(|value| parse_identifier(value).ok_or_else(|| format!("Invalid id: {}", value)))
+        .collect()
+}
+
+/// `GET /api/events` streams updates as Server-Sent Events.
+/// - `filters` from `parse_filters`, only updates for those identifiers are sent.
+/// - `Last-Event-ID` (or `?last_event_id=`) replays missed events still in the log.
+pub async fn stream_events(
+    mut stream: TcpStream,
+    request: HttpRequest,
+    filters: Vec<Identifier>,
+    context: ServerContext,
+) {
+    let last_event_id = request
+        .header("last-event-id")
+        .map(|value| value.to_string())
+        .or_else(|| request.query_values("last_event_id").into_iter().next());
+
+    let log = &context.websocket.updates;
+
+    // Subscribe before reading the log so nothing falls between replay and live events.
+    let mut receiver = log.sender.subscribe();
+    let replay: Vec<(u64, UpdateChange)> = match last_event_id.and_then(|id| log.resume_from(&id)) {
+        Some(after) => log
+            .recent()
+            .1
+            .iter()
+            .filter(|(sequence, _)| *sequence > after)
+            .cloned()
+            .collect(),
+        None => vec![],
+    };
+    let mut last_sent = replay.last().map_or(0, |(sequence, _)| *sequence);
+
+    let head = format!(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/event-stream\r\n\
+         Cache-Control: no-cache\r\n\
+         Connection: keep-alive\r\n\r\n\
+         retry: {}\n\n",
+        RETRY_MS
+    );
+    if stream.write_all(head.as_bytes()).await.is_err() {
+        return;
+    }
+
+    for (sequence, change) in &replay {
+        if !matches_filters(&filters, change) {
+            continue;
+        }
+        if write_event(&mut stream, &log.event_id(*sequence), change)
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+
+    let mut keep_alive = interval(KEEP_ALIVE);
+    keep_alive.tick().await; // Skip the first immediate tick.
+
+    loop {
+        let result = tokio::select! {
+            received = receiver.recv() => match received {
+                Ok((sequence, _)) if sequence <= last_sent => continue,
+                Ok((sequence, change)) => {
+                    last_sent = sequence;
+                    if !matches_filters(&filters, &change) {
+                        continue;
+                    }
+                    write_event(&mut stream, &log.event_id(sequence), &change).await
+                }
+                // Too slow to keep up, the client has to refetch the state.
+                Err(RecvError::Lagged(skipped)) => {
+                    let comment = format!(": skipped {} events\n\n", skipped);
+                    stream.write_all(comment.as_bytes()).await
+                }
+                Err(RecvError::Closed) => break,
+            },
+            // Writing is the only way to notice a client that went away.
+            _ = keep_alive.tick() => stream.write_all(b": keep-alive\n\n").await,
+        };
+
+        if result.is_err() {
+            break;
+        }
+    }
+}
+
+async fn write_event(
+    stream: &mut TcpStream,
+    id: &str,
+    change: &UpdateChange,
+) -> std::io::Result<()> {
+    let data = serde_json::to_string(change).unwrap_or_default();
+    let event = format!(
+        "id: {}\nevent: {}\ndata: {}\n\n",
+        id, UPDATE_EVENT_NAME, data
+    );
+    stream.write_all(event.as_bytes()).await
+}
+
+fn matches_filters(filters: &[Identifier], change: &UpdateChange) -> bool {
+    filters.is_empty() || filters.contains(&change.id)
+}
+
+/// `app:<pid>` or `device:<id>`.
+fn parse_identifier(value: &str) -> Option<Identifier> {
+    match value.split_once(':')? {
+        ("app", id) => id.parse().ok().map(Identifier::App),
+        ("device", id) => Some(Identifier::Device(id.to_string())),
+        _ => None,
+    }
+}
@@ -1,22 +1,28 @@
 use futures_util::future::{select, Either};
 use serde_json::json;
+use shared_types::{AppIdentifier, AudioApplication, AudioDevice};
+use std::collections::HashMap;
 use std::sync::mpsc::{Receiver, Sender};
 use std::time::Duration;
-use tauri::{async_runtime as rt, AppHandle, Emitter, EventTarget, Manager};
-use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 use tokio::time::interval;
+use tokio_tungstenite::tungstenite::Message;
 
-use crate::server::WebSocketServerState;
+use crate::server::{rules, runtime as rt, ServerContext};
 use crate::types::shared::UPDATE_EVENT_NAME;
 use crate::{
     platform,
     types::{
-        shared::{UpdateChange, VolumeControllerError, VolumeControllerTrait},
+        app_volumes::SavedVolume,
+        shared::{
+            ChangeType, EntityState, Identifier, UpdateChange, VolumeControllerError,
+            VolumeControllerTrait, VolumeResult,
+        },
         volume::{VolumeCommand, VolumeCommandSender, VolumeServer},
     },
 };
 
-pub fn spawn_volume_thread(app_handle: &AppHandle, sender: Sender<UpdateChange>) {
+pub fn spawn_volume_thread(context: &ServerContext, sender: Sender<UpdateChange>) {
     let (tx, mut rx) = unbounded_channel::<VolumeCommand>();
 
     let thread_handle = std::thread::spawn(move || {
@@ -56,7 +62,7 @@ pub fn spawn_volume_thread(app_handle: &AppHandle, sender: Sender<UpdateChange>)
         thread_handle: Some(thread_handle),
     };
 
-    let state = app_handle.state::<VolumeCommandSender>();
+    let state = &context.volume;
     let current_server = match state.server.lock() {
         Ok(mut current) => current.replace(new_server),
         Err(_) => None,
@@ -69,29 +75,41 @@ pub fn spawn_volume_thread(app_handle: &AppHandle, sender: Sender<UpdateChange>)
     }
 }
 
-pub fn spawn_update_thread(app_handle: &AppHandle, sender: Receiver<UpdateChange>) {
-    let app_handle = app_handle.clone();
+pub fn spawn_update_thread(context: &ServerContext, sender: Receiver<UpdateChange>) {
+    let context = context.clone();
 
     std::thread::spawn(move || {
+        context.app_volumes.load();
+        let mut apps = AppCache::default();
+
         while let Ok(msg) = sender.recv() {
             println!("sending: {:?}", msg);
+            apps.before_update(&msg);
+            remember_app_volume(&context, &msg, &mut apps);
+            rules::handle_update(&context, &msg, &mut apps);
+            apps.after_update(&msg);
 
             // ==================== SEND TO WEBVIEW ====================
-            let target_event = EventTarget::labeled("volume-control-panel");
-            let result = app_handle.emit_to(target_event, UPDATE_EVENT_NAME, &msg);
-            if let Err(err) = result {
-                eprintln!("Error emitting update event: {}", err);
-            }
+            context.events.update(&msg);
+            // ================== SEND TO SSE CLIENTS ==================
+            let websocket_server = &context.websocket;
+            websocket_server.updates.publish(&msg);
             // =============== SEND TO WEBSOCKET CLIENTS ===============
             let event_str = json! ({
                 "event": UPDATE_EVENT_NAME,
                 "payload": &msg
             })
             .to_string();
-            let websocket_server = app_handle.state::<WebSocketServerState>();
             let clients = websocket_server.clients.blocking_lock();
-            for (_id, client) in clients.iter() {
-                let _ = client.1.send(event_str.clone().into());
+            for (_id, (_, client_sender)) in clients.iter() {
+                let message = Message::from(event_str.clone());
+                let _ = match msg.change {
+                    // Only the latest volume matters for a client that is falling behind.
+                    ChangeType::AudioVolume { .. } => {
+                        client_sender.send_collapsible(msg.id.clone(), message)
+                    }
+                    _ => client_sender.send(message),
+                };
             }
             // ====================== RECEIVE END ======================
         }
@@ -99,6 +117,144 @@ pub fn spawn_update_thread(app_handle: &AppHandle, sender: Receiver<UpdateChange
     });
 }
 
+// ===================== APP CACHE =====================
+/// An app seen on the update thread.
+#[derive(Debug, Clone)]
+pub struct AppInfo {
+    pub name: String,
+    /// `None` when the executable path isn't known.
+    pub path: Option<String>,
+}
+
+/// Apps by pid, shared by `remember_app_volume` and the rules on the update thread.
+/// - Each session is looked up once, `None` is kept for pids the volume thread doesn't know.
+/// - Kept until the session goes away, so it can still be matched when it does.
+#[derive(Default)]
+pub struct AppCache {
+    apps: HashMap<AppIdentifier, Option<AppInfo>>,
+}
+
+impl AppCache {
+    /// Looked up when the pid wasn't seen yet, blocks until the volume thread answers.
+    pub fn get(&mut self, context: &ServerContext, pid: AppIdentifier) -> Option<&AppInfo> {
+        self.apps
+            .entry(pid)
+            .or_insert_with(|| lookup_app(context, pid))
+            .as_ref()
+    }
+
+    /// What is known without asking the volume thread.
+    pub fn cached(&self, pid: AppIdentifier) -> Option<&AppInfo> {
+        self.apps.get(&pid).and_then(Option::as_ref)
+    }
+
+    /// Pids get reused, a new session is looked up again.
+    fn before_update(&mut self, msg: &UpdateChange) {
+        if let Some((pid, EntityState::Created)) = app_state(msg) {
+            self.apps.remove(&pid);
+        }
+    }
+
+    fn after_update(&mut self, msg: &UpdateChange) {
+        if let Some((pid, EntityState::Disconnect)) = app_state(msg) {
+            self.apps.remove(&pid);
+        }
+    }
+}
+
+fn app_state(msg: &UpdateChange) -> Option<(AppIdentifier, &EntityState)> {
+    match (&msg.id, &msg.change) {
+        (Identifier::App(pid), ChangeType::StateChange { state }) => Some((*pid, state)),
+        _ => None,
+    }
+}
+
+/// Asks the volume thread, blocks until it answers.
+fn lookup_app(context: &ServerContext, pid: AppIdentifier) -> Option<AppInfo> {
+    let (tx, mut rx) = unbounded_channel();
+    let command = VolumeCommand::GetApplication {
+        request_id: String::new(),
+        id: pid,
+        sender: tx,
+    };
+    context.volume.send(command).ok()?;
+
+    let app = rx.blocking_recv()?.ok()?;
+    Some(AppInfo {
+        name: app.process.name,
+        path: app.process.path.filter(|path| !path.is_empty()),
+    })
+}
+
+// ================ REMEMBER APP VOLUMES ================
+/// Keeps `AppVolumes` up to date and restores an app's volume when its session is created.
+/// - Saved to disk when an app goes away, not on every slider move.
+fn remember_app_volume(context: &ServerContext, msg: &UpdateChange, apps: &mut AppCache) {
+    let pid = match msg.id {
+        Identifier::App(pid) => pid,
+        Identifier::Device(_) => return,
+    };
+
+    let remember = context
+        .storage
+        .inspect(|settings| settings.remember_app_volumes);
+    if !remember {
+        return;
+    }
+
+    match &msg.change {
+        ChangeType::StateChange {
+            state: EntityState::Created,
+        } => {
+            let path = apps.get(context, pid).and_then(|app| app.path.as_deref());
+            if let Some(saved) = path.and_then(|path| context.app_volumes.get(path)) {
+                restore_app_volume(context, pid, saved);
+            }
+        }
+        ChangeType::StateChange {
+            state: EntityState::Disconnect,
+        } => {
+            if let Err(e) = context.app_volumes.save() {
+                eprintln!("[remember_app_volume] Failed to save app volumes: {}", e);
+            }
+        }
+        ChangeType::AudioVolume { volume, mute } => {
+            if let Some(path) = apps.get(context, pid).and_then(|app| app.path.as_deref()) {
+                let value = SavedVolume {
+                    volume: *volume,
+                    mute: *mute,
+                };
+                context.app_volumes.set(path, value);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn restore_app_volume(context: &ServerContext, pid: AppIdentifier, saved: SavedVolume) {
+    let volume = VolumeCommand::ApplicationSetVolume {
+        request_id: String::new(),
+        id: pid,
+        volume: saved.volume,
+    };
+    let mute = match saved.mute {
+        true => VolumeCommand::ApplicationMute {
+            request_id: String::new(),
+            id: pid,
+        },
+        false => VolumeCommand::ApplicationUnmute {
+            request_id: String::new(),
+            id: pid,
+        },
+    };
+
+    for command in [volume, mute] {
+        if let Err(e) = context.volume.send(command) {
+            eprintln!("[restore_app_volume] {}", e);
+        }
+    }
+}
+
 fn execute_command(command: VolumeCommand, controller: &Box<dyn VolumeControllerTrait>) {
     match command {
         // Master Controll
@@ -159,3 +315,64 @@ fn execute_command(command: VolumeCommand, controller: &Box<dyn VolumeController
         }
     }
 }
+
+// ================ VOLUME THREAD REQUESTS ================
+/// Sends a command built around a reply channel and waits for the answer.
+pub async fn request<T>(
+    volume: &VolumeCommandSender,
+    command: impl FnOnce(UnboundedSender<VolumeResult<T>>) -> VolumeCommand,
+) -> VolumeResult<T> {
+    let (tx, mut rx) = unbounded_channel();
+    volume
+        .send(command(tx))
+        .map_err(VolumeControllerError::Unavailable)?;
+
+    match rx.recv().await {
+        Some(result) => result,
+        None => Err(VolumeControllerError::Unavailable(
+            "Response channel closed".into(),
+        )),
+    }
+}
+
+pub async fn playback_devices(volume: &VolumeCommandSender) -> Result<Vec<AudioDevice>, String> {
+    request(volume, |sender| VolumeCommand::GetPlaybackDevices {
+        request_id: String::new(),
+        sender,
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Apps that go away while they are being listed are left out.
+pub async fn running_apps(
+    volume: &VolumeCommandSender,
+    devices: &[AudioDevice],
+) -> Vec<AudioApplication> {
+    let mut apps = vec![];
+
+    for device in devices {
+        let pids = request(volume, |sender| VolumeCommand::GetDeviceApplications {
+            request_id: String::new(),
+            id: device.id.clone(),
+            sender,
+        })
+        .await
+        .unwrap_or_default();
+
+        for id in pids {
+            let app = request(volume, |sender| VolumeCommand::GetApplication {
+                request_id: String::new(),
+                id,
+                sender,
+            })
+            .await;
+
+            if let Ok(app) = app {
+                apps.push(app);
+            }
+        }
+    }
+
+    apps
+}
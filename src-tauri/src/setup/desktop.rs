@@ -1,9 +1,6 @@
 use tauri::{Manager, Result as TauriResult};
 
-use crate::{
-    server::{ServiceDiscovery, WebSocketServerState},
-    types::{click::DoubleClickState, storage::Storage, volume::VolumeCommandSender},
-};
+use crate::types::{click::DoubleClickState, storage::Storage};
 
 use crate::commands;
 
@@ -15,15 +12,12 @@ pub fn create_tauri_app() -> TauriResult<tauri::App> {
         .plugin(tauri_plugin_websocket::init())
         .plugin(tauri_plugin_os::init())
         .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_notification::init())
         .plugin(tauri_plugin_autostart::init(
             tauri_plugin_autostart::MacosLauncher::default(),
             None,
         ))
-        .manage(VolumeCommandSender::new())
-        .manage(WebSocketServerState::default())
-        .manage(ServiceDiscovery::default())
         .manage(DoubleClickState::new(None))
-        .manage(Storage::default())
         .setup(super::setup)
         .on_menu_event(super::menu_event)
         .on_window_event(|_window, _event| {
@@ -57,8 +51,34 @@ pub fn create_tauri_app() -> TauriResult<tauri::App> {
             // Device controls
             commands::get_playback_devices,
             commands::get_device_applications,
+            // Connected clients
+            commands::get_connected_clients,
+            commands::disconnect_client,
+            // Server
+            commands::get_server_addresses,
+            commands::change_server_port,
+            commands::get_discovery_status,
+            // Settings
+            commands::get_settings,
+            commands::update_settings,
+            commands::get_settings_load_report,
+            // Profiles
+            commands::list_profiles,
+            commands::get_profile,
+            commands::save_profile,
+            commands::apply_profile,
+            commands::delete_profile,
+            // Rules
+            commands::list_rules,
+            commands::save_rule,
+            commands::delete_rule,
+            // Backup
+            commands::export_bundle,
+            commands::preview_import,
+            commands::import_bundle,
             // Miscellaneous
-            commands::discover_server_address
+            commands::discover_server_address,
+            commands::discover_servers
         ])
         .build(tauri::generate_context!())
 }
@@ -0,0 +1,105 @@
+use tauri::{AppHandle, Emitter, EventTarget, Manager};
+use tauri_plugin_notification::NotificationExt;
+
+use crate::{
+    server::{ClientInfo, DiscoveryStatus, ServerEvents},
+    types::{
+        shared::{
+            UpdateChange, CLIENTS_EVENT_NAME, CLIENT_CONNECTED_EVENT_NAME,
+            CLIENT_DISCONNECTED_EVENT_NAME, DISCOVERY_EVENT_NAME, PROFILES_EVENT_NAME,
+            SETTINGS_EVENT_NAME, UPDATE_EVENT_NAME,
+        },
+        storage::{Settings, Storage},
+    },
+};
+
+/// Forwards server events to the webview, the tray listens to them as well.
+pub struct TauriEvents {
+    app_handle: AppHandle,
+}
+
+impl TauriEvents {
+    pub fn new(app_handle: &AppHandle) -> Self {
+        Self {
+            app_handle: app_handle.clone(),
+        }
+    }
+
+    fn emit(&self, event: &str, client: &ClientInfo) {
+        if let Err(err) = self.app_handle.emit(event, client) {
+            eprintln!("Error emitting {} event: {}", event, err);
+        }
+    }
+
+    /// Shown unless `client_notifications` is turned off in the settings.
+    fn notify(&self, body: String) {
+        let enabled = self
+            .app_handle
+            .state::<Storage>()
+            .get()
+            .client_notifications;
+        if !enabled {
+            return;
+        }
+
+        let result = self
+            .app_handle
+            .notification()
+            .builder()
+            .title("Volumize")
+            .body(body)
+            .show();
+        if let Err(err) = result {
+            eprintln!("Error showing notification: {}", err);
+        }
+    }
+}
+
+impl ServerEvents for TauriEvents {
+    fn update(&self, change: &UpdateChange) {
+        let target_event = EventTarget::labeled("volume-control-panel");
+        let result = self
+            .app_handle
+            .emit_to(target_event, UPDATE_EVENT_NAME, change);
+        if let Err(err) = result {
+            eprintln!("Error emitting update event: {}", err);
+        }
+    }
+
+    fn clients_changed(&self, clients: &[ClientInfo]) {
+        if let Err(err) = self.app_handle.emit(CLIENTS_EVENT_NAME, clients) {
+            eprintln!("Error emitting clients event: {}", err);
+        }
+    }
+
+    fn client_connected(&self, client: &ClientInfo) {
+        self.emit(CLIENT_CONNECTED_EVENT_NAME, client);
+        self.notify(format!(
+            "{} is now controlling this PC",
+            client.display_name()
+        ));
+    }
+
+    fn client_disconnected(&self, client: &ClientInfo) {
+        self.emit(CLIENT_DISCONNECTED_EVENT_NAME, client);
+        self.notify(format!("{} disconnected", client.display_name()));
+    }
+
+    fn discovery_changed(&self, status: &DiscoveryStatus) {
+        if let Err(err) = self.app_handle.emit(DISCOVERY_EVENT_NAME, status) {
+            eprintln!("Error emitting discovery event: {}", err);
+        }
+    }
+
+    fn settings_changed(&self, settings: &Settings) {
+        if let Err(err) = self.app_handle.emit(SETTINGS_EVENT_NAME, settings) {
+            eprintln!("Error emitting settings event: {}", err);
+        }
+    }
+
+    fn profiles_changed(&self, profiles: &[String]) {
+        if let Err(err) = self.app_handle.emit(PROFILES_EVENT_NAME, profiles) {
+            eprintln!("Error emitting profiles event: {}", err);
+        }
+    }
+}
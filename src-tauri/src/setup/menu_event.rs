@@ -1,21 +1,35 @@
 use std::str::FromStr;
 
-use tauri::{menu::MenuEvent, AppHandle, Manager};
+use tauri::{async_runtime as rt, menu::MenuEvent, AppHandle, Manager};
 use tauri_plugin_autostart::ManagerExt;
 
 use crate::{
-    server::service_register::start_service_register,
-    types::{storage::Storage, tray::Discovery},
+    server::{
+        profiles::apply_profile, service_register::start_service_register,
+        settings::update_settings, ServerContext, WebSocketServerState,
+    },
+    types::tray::Discovery,
 };
 
+use super::system_tray::{APPLY_PROFILE_PREFIX, DISCONNECT_CLIENT_PREFIX};
+
 pub fn menu_event(app: &AppHandle, event: MenuEvent) {
     match event.id().as_ref() {
         "show" => super::setup::show_window_visibility(app),
         "refresh" => {
-            let storage = app.app_handle().state::<Storage>();
-            let settings = storage.get();
+            // Reopens a closed `OnDuration` window.
+            let context = app.state::<ServerContext>();
+            start_service_register(&context, context.discovery.status().chosen);
+        }
+        "exit_to_tray" => {
+            let context = app.state::<ServerContext>();
+            let mut settings = context.storage.get();
+            settings.exit_to_tray = !settings.exit_to_tray;
 
-            start_service_register(settings.port_address, app, settings.duration);
+            // The tray is rebuilt by the settings event.
+            if let Err(e) = update_settings(&context, settings) {
+                eprintln!("{}", e);
+            }
         }
         "auto_start" => {
             let manager = app.autolaunch();
@@ -31,31 +45,49 @@ pub fn menu_event(app: &AppHandle, event: MenuEvent) {
                 eprintln!("{}", e);
             }
         }
+        id if id.starts_with(DISCONNECT_CLIENT_PREFIX) => {
+            let client_id = &id[DISCONNECT_CLIENT_PREFIX.len()..];
+            let state = app.state::<WebSocketServerState>();
+
+            if let Err(e) = rt::block_on(state.disconnect_client(client_id)) {
+                eprintln!("{}", e);
+            }
+        }
+        id if id.starts_with(APPLY_PROFILE_PREFIX) => {
+            let name = id[APPLY_PROFILE_PREFIX.len()..].to_string();
+            let context = app.state::<ServerContext>().inner().clone();
+
+            rt::spawn(async move {
+                match apply_profile(&context, &name).await {
+                    Ok(report) => println!(
+                        "Applied profile {}: {} applied, {} skipped",
+                        name,
+                        report.applied.len(),
+                        report.skipped.len()
+                    ),
+                    Err(e) => eprintln!("{}", e),
+                }
+            });
+        }
         rest => {
             let discover = match Discovery::from_str(rest) {
                 Ok(value) => value,
                 Err(_) => return,
             };
 
-            let sould_save = match discover {
-                Discovery::OnDuration(_) => false,
-                _ => true,
-            };
-
-            let storage = app.app_handle().state::<Storage>();
-            let mut settings = storage.get();
-
-            settings.duration = discover;
-
-            if sould_save {
-                if let Err(err) = storage.save(app, &settings) {
-                    eprintln!("{}", err);
+            // A timer only lasts for this run, it is kept in the discovery status.
+            // Other policies are saved, `update_settings` starts them.
+            let context = app.state::<ServerContext>();
+            let mut settings = context.storage.get();
+            if matches!(discover, Discovery::OnDuration(_)) || settings.duration == discover {
+                start_service_register(&context, discover);
+            } else {
+                settings.duration = discover;
+                if let Err(e) = update_settings(&context, settings) {
+                    eprintln!("{}", e);
                 }
             }
 
-            storage.update(settings);
-            start_service_register(settings.port_address, app, discover);
-
             if let Err(e) = super::setup::setup_tray_system(&app) {
                 eprintln!("{}", e);
             }
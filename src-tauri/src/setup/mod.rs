@@ -1,4 +1,5 @@
 mod desktop;
+mod events;
 mod system_tray;
 
 mod menu_event;
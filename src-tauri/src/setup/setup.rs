@@ -1,18 +1,32 @@
-use std::error::Error;
+use std::{error::Error, sync::Arc, time::Duration};
 
 use crate::{
     server::{
+        ipc::start_ipc_server,
+        rules::spawn_rule_clock,
         service_register::start_service_register,
+        settings::spawn_settings_watcher,
         start_websocket_server,
         volume_control::{spawn_update_thread, spawn_volume_thread},
+        ServerContext, ServiceDiscovery,
+    },
+    types::{
+        shared::{
+            UpdateChange, CLIENTS_EVENT_NAME, DISCOVERY_EVENT_NAME, PROFILES_EVENT_NAME,
+            SETTINGS_EVENT_NAME, SETTINGS_LOAD_EVENT_NAME,
+        },
+        storage::{resolve_config_dir, LoadReport, Storage},
     },
-    types::{shared::UpdateChange, storage::Storage},
 };
 
-use tauri::{App, Manager};
+use tauri::{App, Emitter, Listener, Manager};
+use tauri_plugin_notification::NotificationExt;
+
+const TRAY_COUNTDOWN_INTERVAL: Duration = Duration::from_secs(30);
 
 pub fn setup(app: &mut App) -> Result<(), Box<dyn Error>> {
     let app_handle = app.handle();
+    let context = manage_server_context(app_handle);
 
     #[cfg(debug_assertions)]
     {
@@ -21,25 +35,83 @@ pub fn setup(app: &mut App) -> Result<(), Box<dyn Error>> {
     }
 
     setup_tray_system(app_handle)?;
+    refresh_tray_on_server_events(app_handle);
+    spawn_discovery_countdown(app_handle);
 
-    let storage = app_handle.state::<Storage>();
-    storage.load(app_handle);
-    let settings = storage.get();
+    let report = context.storage.load();
+    report_settings_problems(app_handle, &report);
+    let settings = context.storage.get();
 
     let (tx, rx) = std::sync::mpsc::channel::<UpdateChange>();
-    spawn_volume_thread(app_handle, tx); // Thread for volume control
-    spawn_update_thread(app_handle, rx); // Thread for propagate updates to the UI
+    spawn_volume_thread(&context, tx); // Thread for volume control
+    spawn_update_thread(&context, rx); // Thread for propagate updates to the UI
 
-    match start_websocket_server(settings.port_address, app_handle) {
-        Ok(addr) => println!("WebSocket server listening on {}", addr),
+    match start_websocket_server(&settings, &context) {
+        Ok(addrs) => println!("WebSocket server listening on {:?}", addrs),
         Err(e) => eprintln!("Failed to start WebSocket server: {}", e),
     }
 
-    start_service_register(settings.port_address, app_handle, settings.duration);
+    start_service_register(&context, settings.duration);
+
+    match start_ipc_server(&context) {
+        Ok(endpoint) => println!("Local control endpoint listening on {}", endpoint),
+        Err(e) => eprintln!("Failed to start local control endpoint: {}", e),
+    }
+
+    spawn_settings_watcher(&context);
+    spawn_rule_clock(&context);
 
     Ok(())
 }
 
+/// The window may not be listening yet, the report stays available through
+/// `get_settings_load_report`.
+fn report_settings_problems(app: &tauri::AppHandle, report: &LoadReport) {
+    if report.problems.is_empty() {
+        return;
+    }
+
+    if let Err(e) = app.emit(SETTINGS_LOAD_EVENT_NAME, report) {
+        eprintln!("Error emitting settings load event: {}", e);
+    }
+
+    let body = match &report.backup {
+        Some(path) => format!(
+            "Some settings could not be loaded, the original file was saved to {}",
+            path.display()
+        ),
+        None => "Some settings could not be loaded".to_string(),
+    };
+
+    let result = app
+        .notification()
+        .builder()
+        .title("Volumize")
+        .body(body)
+        .show();
+    if let Err(e) = result {
+        eprintln!("Error showing notification: {}", e);
+    }
+}
+
+/// Server state shared with the commands, each part is managed on its own as well.
+fn manage_server_context(app: &tauri::AppHandle) -> ServerContext {
+    let events = Arc::new(super::events::TauriEvents::new(app));
+    let dir = resolve_config_dir(std::env::args().skip(1));
+    println!("Config directory: {}", dir.display());
+
+    let context = ServerContext::new(Storage::new(dir), events);
+
+    app.manage(context.volume.clone());
+    app.manage(context.websocket.clone());
+    app.manage(context.discovery.clone());
+    app.manage(context.ipc.clone());
+    app.manage(context.storage.clone());
+    app.manage(context.profiles.clone());
+    app.manage(context.clone());
+    context
+}
+
 pub fn setup_tray_system(app: &tauri::AppHandle) -> Result<(), Box<dyn Error>> {
     use std::io::Error;
     use tauri::tray::TrayIconBuilder;
@@ -87,6 +159,46 @@ pub fn setup_tray_system(app: &tauri::AppHandle) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// Keeps the tray menu in sync with clients, discovery, settings and profiles.
+fn refresh_tray_on_server_events(app: &tauri::AppHandle) {
+    for event in [
+        CLIENTS_EVENT_NAME,
+        DISCOVERY_EVENT_NAME,
+        SETTINGS_EVENT_NAME,
+        PROFILES_EVENT_NAME,
+    ] {
+        let handle = app.clone();
+        app.listen(event, move |_event| refresh_tray(&handle));
+    }
+}
+
+/// Updates the time left in the "Server discovery" submenu while a timer runs.
+fn spawn_discovery_countdown(app: &tauri::AppHandle) {
+    let handle = app.clone();
+
+    tauri::async_runtime::spawn(async move {
+        let mut ticker = tokio::time::interval(TRAY_COUNTDOWN_INTERVAL);
+        loop {
+            ticker.tick().await;
+
+            let status = handle.state::<ServiceDiscovery>().status();
+            if status.expires_at.is_some() {
+                refresh_tray(&handle);
+            }
+        }
+    });
+}
+
+fn refresh_tray(app: &tauri::AppHandle) {
+    // Events are emitted from the async runtime, rebuild the menu on the main thread.
+    let tray_handle = app.clone();
+    let _ = app.run_on_main_thread(move || {
+        if let Err(e) = setup_tray_system(&tray_handle) {
+            eprintln!("{}", e);
+        }
+    });
+}
+
 #[cfg(debug_assertions)]
 fn setup_dev_tools(app: &tauri::AppHandle) {
     for window_config in &app.config().app.windows {
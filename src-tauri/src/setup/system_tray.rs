@@ -1,14 +1,20 @@
 use std::time::Duration;
 
 use tauri::{
+    async_runtime as rt,
     menu::{CheckMenuItemBuilder, Menu, MenuItem, PredefinedMenuItem, Submenu, SubmenuBuilder},
     {Manager, Result as TauriResult, Wry},
 };
 use tauri_plugin_autostart::ManagerExt;
 
+use crate::server::{DiscoveryStatus, ServiceDiscovery, WebSocketServerState};
+use crate::types::profiles::Profiles;
 use crate::types::storage::Storage;
 use crate::types::tray::Discovery;
 
+pub const DISCONNECT_CLIENT_PREFIX: &str = "disconnect_client:";
+pub const APPLY_PROFILE_PREFIX: &str = "apply_profile:";
+
 pub fn create_tray(handle: &tauri::AppHandle) -> TauriResult<Menu<Wry>> {
     let show = MenuItem::with_id(handle, "show", "Show", true, None::<&str>)?;
     let refresh_token = MenuItem::with_id(handle, "refresh", "Quick refresh", true, None::<&str>)?;
@@ -31,6 +37,8 @@ pub fn create_tray(handle: &tauri::AppHandle) -> TauriResult<Menu<Wry>> {
     let _ = tray_menu.append(&separator);
     let _ = tray_menu.append(&auto_start_sub_menu(handle)?);
     let _ = tray_menu.append(&discovery_sub_menu(handle)?);
+    let _ = tray_menu.append(&profiles_sub_menu(handle)?);
+    let _ = tray_menu.append(&clients_sub_menu(handle)?);
     let _ = tray_menu.append(&separator);
     let _ = tray_menu.append(&quit);
 
@@ -118,26 +126,99 @@ fn app_version(handle: &tauri::AppHandle) -> tauri::Result<MenuItem<Wry>> {
     )
 }
 
+/// Checks the policy in effect, an expired timer shows as turned off.
 fn discovery_sub_menu(handle: &tauri::AppHandle) -> tauri::Result<Submenu<Wry>> {
-    let settings = handle.state::<Storage>().get();
+    let status = handle.state::<ServiceDiscovery>().status();
+    let policy = status.policy;
 
-    let info_text = format!("Status: {}", settings.duration.display());
+    let info_text = format!("Status: {}", discovery_status_text(&status));
     let status_info = MenuItem::with_id(handle, "show", info_text, false, None::<&str>)?;
 
-    let always_off = checked_menu_item(Discovery::TurnOff, settings.duration).build(handle)?;
-    let always_on = checked_menu_item(Discovery::AlwaysOn, settings.duration).build(handle)?;
+    let always_off = checked_menu_item(Discovery::TurnOff, policy).build(handle)?;
+    let always_on = checked_menu_item(Discovery::AlwaysOn, policy).build(handle)?;
 
     SubmenuBuilder::new(handle, "Server discovery")
         .item(&status_info)
         .item(&PredefinedMenuItem::separator(handle)?)
         .item(&always_on)
-        .item(&timer_submenu(15, settings.duration).build(handle)?)
-        .item(&timer_submenu(5, settings.duration).build(handle)?)
-        .item(&timer_submenu(2, settings.duration).build(handle)?)
+        .item(&timer_submenu(15, policy).build(handle)?)
+        .item(&timer_submenu(5, policy).build(handle)?)
+        .item(&timer_submenu(2, policy).build(handle)?)
         .item(&always_off)
         .build()
 }
 
+fn discovery_status_text(status: &DiscoveryStatus) -> String {
+    let remaining = match status.remaining() {
+        Some(remaining) => remaining,
+        None => return status.policy.display(),
+    };
+
+    match remaining.as_secs().div_ceil(60) {
+        0 | 1 => "On, 1 minute left".into(),
+        minutes => format!("On, {} minutes left", minutes),
+    }
+}
+
+fn clients_sub_menu(handle: &tauri::AppHandle) -> tauri::Result<Submenu<Wry>> {
+    let state = handle.state::<WebSocketServerState>();
+    let clients = rt::block_on(state.list_clients());
+
+    let title = format!("Connected clients ({})", clients.len());
+    let mut builder = SubmenuBuilder::new(handle, title);
+
+    if clients.is_empty() {
+        let empty = MenuItem::with_id(handle, "no_clients", "None", false, None::<&str>)?;
+        builder = builder.item(&empty);
+    }
+
+    for client in &clients {
+        let address = MenuItem::with_id(
+            handle,
+            "",
+            format!("Address: {}", client.address),
+            false,
+            None::<&str>,
+        )?;
+
+        let disconnect = MenuItem::with_id(
+            handle,
+            format!("{}{}", DISCONNECT_CLIENT_PREFIX, client.id),
+            "Disconnect",
+            true,
+            None::<&str>,
+        )?;
+
+        let client_menu = SubmenuBuilder::new(handle, client.display_name())
+            .item(&address)
+            .item(&PredefinedMenuItem::separator(handle)?)
+            .item(&disconnect)
+            .build()?;
+
+        builder = builder.item(&client_menu);
+    }
+
+    builder.build()
+}
+
+fn profiles_sub_menu(handle: &tauri::AppHandle) -> tauri::Result<Submenu<Wry>> {
+    let names = handle.state::<Profiles>().names();
+    let mut builder = SubmenuBuilder::new(handle, "Profiles");
+
+    if names.is_empty() {
+        let empty = MenuItem::with_id(handle, "no_profiles", "None", false, None::<&str>)?;
+        builder = builder.item(&empty);
+    }
+
+    for name in &names {
+        let id = format!("{}{}", APPLY_PROFILE_PREFIX, name);
+        let apply = MenuItem::with_id(handle, id, name, true, None::<&str>)?;
+        builder = builder.item(&apply);
+    }
+
+    builder.build()
+}
+
 fn checked_menu_item(item: Discovery, settings: Discovery) -> CheckMenuItemBuilder {
     CheckMenuItemBuilder::with_id(Discovery::to_string(&item), Discovery::display(&item))
         .checked(settings == item)
@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    io::ErrorKind,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use super::storage::write_atomic;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct SavedVolume {
+    pub volume: f32,
+    pub mute: bool,
+}
+
+#[derive(Default)]
+struct Saved {
+    /// Keyed by executable path, pids change every time an app starts.
+    volumes: HashMap<String, SavedVolume>,
+    dirty: bool,
+}
+
+/// Last volume and mute state of every app, see `Settings::remember_app_volumes`.
+/// - Kept in `app_volumes.json` next to the settings, shared by every clone.
+#[derive(Clone)]
+pub struct AppVolumes {
+    saved: Arc<Mutex<Saved>>,
+    path: PathBuf,
+}
+
+impl AppVolumes {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            saved: Default::default(),
+            path: dir.join("app_volumes.json"),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Saved> {
+        match self.saved.lock() {
+            Ok(saved) => saved,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    /// A missing or unreadable file starts out empty.
+    pub fn load(&self) {
+        let volumes = match fs::read(&self.path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                eprintln!("[AppVolumes::load] Ignoring {}: {}", self.path.display(), e);
+                HashMap::new()
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                eprintln!(
+                    "[AppVolumes::load] Failed to read {}: {}",
+                    self.path.display(),
+                    e
+                );
+                HashMap::new()
+            }
+        };
+
+        *self.lock() = Saved {
+            volumes,
+            dirty: false,
+        };
+    }
+
+    pub fn all(&self) -> HashMap<String, SavedVolume> {
+        self.lock().volumes.clone()
+    }
+
+    pub fn get(&self, app_path: &str) -> Option<SavedVolume> {
+        self.lock().volumes.get(app_path).copied()
+    }
+
+    /// Only kept in memory until the next `save`.
+    pub fn set(&self, app_path: &str, value: SavedVolume) {
+        let mut saved = self.lock();
+        if saved.volumes.get(app_path) != Some(&value) {
+            saved.volumes.insert(app_path.to_string(), value);
+            saved.dirty = true;
+        }
+    }
+
+    /// Writes the file when something changed since the last save.
+    pub fn save(&self) -> std::io::Result<()> {
+        let mut saved = self.lock();
+        if !saved.dirty {
+            return Ok(());
+        }
+
+        let data = serde_json::to_vec_pretty(&saved.volumes)?;
+        write_atomic(&self.path, &data)?;
+
+        saved.dirty = false;
+        Ok(())
+    }
+}
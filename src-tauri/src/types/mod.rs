@@ -1,4 +1,7 @@
+pub mod app_volumes;
 pub mod click;
+pub mod profiles;
+pub mod rules;
 pub mod shared;
 pub mod storage;
 pub mod tray;
@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{Error, ErrorKind},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use super::storage::write_atomic;
+
+/// Named snapshot of the master and per-app volumes.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub devices: Vec<ProfileEntry>,
+    pub apps: Vec<ProfileEntry>,
+}
+
+/// `id` is the device id, or the executable path of an app.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProfileEntry {
+    pub id: String,
+    pub name: String,
+    pub volume: f32,
+    pub mute: bool,
+}
+
+/// Names of the profile entries that were sent to a device or running app, and of
+/// those that were left alone because nothing matched.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ProfileReport {
+    pub profile: String,
+    pub applied: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+/// Every profile in `profiles.json` in the config directory, sorted by name.
+/// - Read on every call, the file is small and changes rarely.
+#[derive(Clone)]
+pub struct Profiles {
+    path: PathBuf,
+    /// Keeps a read-modify-write from losing another one.
+    write: Arc<Mutex<()>>,
+}
+
+impl Profiles {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            path: dir.join("profiles.json"),
+            write: Default::default(),
+        }
+    }
+
+    pub fn all(&self) -> std::io::Result<BTreeMap<String, Profile>> {
+        match fs::read(&self.path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(Error::from),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Empty when the file can't be read.
+    pub fn names(&self) -> Vec<String> {
+        match self.all() {
+            Ok(profiles) => profiles.into_keys().collect(),
+            Err(e) => {
+                eprintln!("[Profiles::names] {}", e);
+                vec![]
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> std::io::Result<Option<Profile>> {
+        Ok(self.all()?.remove(name))
+    }
+
+    /// Replaces a profile with the same name.
+    pub fn insert(&self, profile: Profile) -> std::io::Result<()> {
+        self.modify(|profiles| {
+            profiles.insert(profile.name.clone(), profile);
+            true
+        })
+    }
+
+    /// Returns whether the profile existed.
+    pub fn remove(&self, name: &str) -> std::io::Result<bool> {
+        let mut removed = false;
+        self.modify(|profiles| {
+            removed = profiles.remove(name).is_some();
+            removed
+        })?;
+        Ok(removed)
+    }
+
+    /// Saves when `change` returns true.
+    fn modify(
+        &self,
+        change: impl FnOnce(&mut BTreeMap<String, Profile>) -> bool,
+    ) -> std::io::Result<()> {
+        let _guard = match self.write.lock() {
+            Ok(guard) => guard,
+            Err(e) => e.into_inner(),
+        };
+
+        let mut profiles = self.all()?;
+        if !change(&mut profiles) {
+            return Ok(());
+        }
+
+        let data = serde_json::to_vec_pretty(&profiles)?;
+        write_atomic(&self.path, &data)
+    }
+}
@@ -0,0 +1,197 @@
+use serde::{Deserialize, Serialize};
+use std::{ffi::OsStr, path::Path};
+
+use super::volume::VolumeCommand;
+
+/// Runs `actions` when `trigger` fires and every condition holds.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Rule {
+    /// Assigned when the rule is first saved.
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    pub trigger: Trigger,
+    /// Checked against the app that fired the trigger.
+    /// - For triggers without an app, a condition holds when any running app matches it.
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    pub actions: Vec<RuleAction>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Trigger {
+    /// An app opened an audio session.
+    AppCreated,
+    /// An app's audio session went away.
+    AppExpired,
+    /// An app's audio session started playing.
+    AppActive,
+    /// A playback device was plugged in or enabled.
+    DeviceAdded,
+    /// Another device became the default playback device.
+    DefaultDeviceChanged,
+    /// Every day at this local time.
+    TimeOfDay { hour: u32, minute: u32 },
+}
+
+impl Trigger {
+    pub fn has_app(&self) -> bool {
+        matches!(
+            self,
+            Trigger::AppCreated | Trigger::AppExpired | Trigger::AppActive
+        )
+    }
+
+    pub fn has_device(&self) -> bool {
+        matches!(self, Trigger::DeviceAdded | Trigger::DefaultDeviceChanged)
+    }
+}
+
+/// Case-insensitive match on a running app.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Condition {
+    /// The display name, or the executable's file name with or without its extension.
+    ProcessName { name: String },
+    /// The full executable path.
+    ProcessPath { path: String },
+}
+
+impl Condition {
+    pub fn matches(&self, name: &str, path: Option<&str>) -> bool {
+        match self {
+            Condition::ProcessName { name: wanted } => {
+                let file = path.map(Path::new);
+                let file_name = file.and_then(|file| file.file_name());
+                let file_stem = file.and_then(|file| file.file_stem());
+
+                [Some(OsStr::new(name)), file_name, file_stem]
+                    .into_iter()
+                    .flatten()
+                    .any(|candidate| candidate.to_string_lossy().eq_ignore_ascii_case(wanted))
+            }
+            Condition::ProcessPath { path: wanted } => {
+                path.is_some_and(|path| path.eq_ignore_ascii_case(wanted))
+            }
+        }
+    }
+}
+
+/// A command to send, and which app or device it is sent to.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RuleAction {
+    #[serde(default)]
+    pub target: ActionTarget,
+    /// Any command that changes a volume, see `VolumeCommand::is_action`.
+    /// - Its `id` is replaced unless `target` is `Command`.
+    pub command: VolumeCommand,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ActionTarget {
+    /// The `id` in the command as written.
+    #[default]
+    Command,
+    /// The app or device that fired the trigger.
+    Trigger,
+    /// Every running app matching the condition, nothing when none is running.
+    Matching { condition: Condition },
+}
+
+impl Rule {
+    /// Returns the first problem found.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Rule name can't be empty".into());
+        }
+
+        if let Trigger::TimeOfDay { hour, minute } = self.trigger {
+            if hour > 23 || minute > 59 {
+                return Err(format!("Rule \"{}\": invalid time of day", self.name));
+            }
+        }
+
+        if self.actions.is_empty() {
+            return Err(format!("Rule \"{}\" has no actions", self.name));
+        }
+
+        for action in &self.actions {
+            if !action.command.is_action() {
+                return Err(format!(
+                    "Rule \"{}\": {} can't be used as an action",
+                    self.name,
+                    action.command.get_name()
+                ));
+            }
+
+            let is_app_command = matches!(
+                action.command,
+                VolumeCommand::ApplicationSetVolume { .. }
+                    | VolumeCommand::ApplicationMute { .. }
+                    | VolumeCommand::ApplicationUnmute { .. }
+            );
+            let fits = match action.target {
+                ActionTarget::Command => true,
+                ActionTarget::Trigger if is_app_command => self.trigger.has_app(),
+                ActionTarget::Trigger => self.trigger.has_device(),
+                ActionTarget::Matching { .. } => is_app_command,
+            };
+            if !fits {
+                return Err(format!(
+                    "Rule \"{}\": {} doesn't fit its target",
+                    self.name,
+                    action.command.get_name()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(name: &str) -> Condition {
+        Condition::ProcessName { name: name.into() }
+    }
+
+    fn path(path: &str) -> Condition {
+        Condition::ProcessPath { path: path.into() }
+    }
+
+    #[test]
+    fn process_name_matches_display_name_or_file() {
+        let spotify = Some("C:/Program Files/Spotify/Spotify.exe");
+
+        assert!(name("spotify music").matches("Spotify Music", spotify));
+        assert!(name("spotify.exe").matches("Spotify Music", spotify));
+        assert!(name("SPOTIFY").matches("Spotify Music", spotify));
+        assert!(!name("discord").matches("Spotify Music", spotify));
+        assert!(!name("program files").matches("Spotify Music", spotify));
+    }
+
+    #[test]
+    fn process_name_without_path_uses_display_name() {
+        assert!(name("system sounds").matches("System Sounds", None));
+        assert!(!name("spotify").matches("System Sounds", None));
+    }
+
+    #[test]
+    fn process_path_matches_the_full_path() {
+        let spotify = Some("C:/Program Files/Spotify/Spotify.exe");
+
+        assert!(path("c:/program files/spotify/spotify.exe").matches("Spotify", spotify));
+        assert!(!path("Spotify.exe").matches("Spotify", spotify));
+        assert!(!path("C:/Program Files/Spotify/Spotify.exe").matches("Spotify", None));
+    }
+}
@@ -3,8 +3,16 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 pub const UPDATE_EVENT_NAME: &str = "update";
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
+pub const CLIENTS_EVENT_NAME: &str = "clients";
+pub const CLIENT_CONNECTED_EVENT_NAME: &str = "client_connected";
+pub const CLIENT_DISCONNECTED_EVENT_NAME: &str = "client_disconnected";
+pub const DISCOVERY_EVENT_NAME: &str = "discovery";
+pub const PROFILES_EVENT_NAME: &str = "profiles";
+pub const SETTINGS_EVENT_NAME: &str = "settings";
+pub const SETTINGS_LOAD_EVENT_NAME: &str = "settings_load_problems";
+pub const SERVER_MOVED_EVENT_NAME: &str = "server_moved";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(tag = "type", content = "content", rename_all = "lowercase")]
 pub enum Identifier {
     App(AppIdentifier),
@@ -25,6 +33,10 @@ pub enum ChangeType {
 pub enum EntityState {
     Disconnect,
     Created,
+    /// An app session started playing.
+    Active,
+    /// The device became the default playback device.
+    Default,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -80,6 +92,8 @@ pub enum VolumeControllerError {
     WindowsApiError(#[from] windows::core::Error),
     #[error("Serialization/deserialization error: {0}")]
     SerdeError(#[from] serde_json::Error),
+    #[error("Volume thread unavailable: {0}")]
+    Unavailable(String),
     #[error("Unknown error: {0}")]
     Unknown(String),
 }
@@ -1,46 +1,311 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::{
+    ffi::OsString,
     fs,
-    path::PathBuf,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use tauri::{AppHandle, Manager};
+use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+use super::{rules::Rule, tray::Discovery};
+
+const MAX_DISCOVERY_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+/// Fits in a single DNS label.
+const MAX_SERVER_NAME_LENGTH: usize = 63;
+
+/// Same as `--config-dir`, the flag wins when both are given.
+pub const CONFIG_DIR_ENV: &str = "VOLUMIZE_CONFIG_DIR";
+/// A file with this name next to the executable turns on portable mode, same as `--portable`.
+const PORTABLE_MARKER: &str = "portable";
+/// Config directory next to the executable in portable mode.
+const PORTABLE_DIR: &str = "volumize-data";
+/// Tauri's identifier from `tauri.conf.json`, names the app's own config directory on iOS.
+#[cfg(target_os = "ios")]
+const APP_IDENTIFIER: &str = "com.volumize.rainy";
+
+/// Bumped together with a new entry in `MIGRATIONS`.
+pub const SETTINGS_VERSION: u32 = 1;
+
+/// `MIGRATIONS[n]` turns a version `n` settings file into version `n + 1`.
+const MIGRATIONS: [fn(&mut Map<String, Value>); SETTINGS_VERSION as usize] = [
+    // 0 -> 1: files written before versioning, nothing but the version is new.
+    |_| {},
+];
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
 pub struct Settings {
-    pub duration: super::tray::Discovery,
+    /// Schema version of the file, see `SETTINGS_VERSION`.
+    pub version: u32,
+    /// Tells servers apart on the network, generated on first run.
+    pub server_id: String,
+    /// Shown to clients, empty uses the hostname.
+    pub server_name: String,
+    pub duration: Discovery,
     pub port_address: u16,
+    /// IP addresses or interface names to listen on.
+    /// - Empty listens on every IPv4 and IPv6 interface.
+    pub bind_addresses: Vec<String>,
+    /// Try the next few ports when `port_address` is taken.
+    pub port_fallback: bool,
     pub exit_to_tray: bool,
+    /// Desktop notification when a client connects or disconnects.
+    pub client_notifications: bool,
+    pub heartbeat: Heartbeat,
+    pub compression: Compression,
+    /// Restore each app's last volume and mute state when it starts again.
+    pub remember_app_volumes: bool,
+    pub rules: Vec<Rule>,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Self {
+            version: SETTINGS_VERSION,
+            server_id: String::new(),
+            server_name: String::new(),
             duration: Default::default(),
             port_address: 9002,
+            bind_addresses: vec![],
+            port_fallback: true,
             exit_to_tray: true,
+            client_notifications: true,
+            heartbeat: Default::default(),
+            compression: Default::default(),
+            remember_app_volumes: false,
+            rules: vec![],
+        }
+    }
+}
+
+impl Settings {
+    pub fn display_name(&self) -> String {
+        match self.server_name.trim() {
+            "" => gethostname::gethostname().to_string_lossy().into_owned(),
+            name => name.to_string(),
+        }
+    }
+
+    /// Checks values that can come from a client, returns the first problem found.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.port_address == 0 {
+            return Err("Port must be between 1 and 65535".into());
+        }
+
+        if let Discovery::OnDuration(duration) = self.duration {
+            if duration.is_zero() || duration > MAX_DISCOVERY_DURATION {
+                return Err(format!(
+                    "Discovery duration must be between 1 second and {} hours",
+                    MAX_DISCOVERY_DURATION.as_secs() / 3600
+                ));
+            }
+        }
+
+        if self.server_name.chars().count() > MAX_SERVER_NAME_LENGTH {
+            return Err(format!(
+                "Server name can't be longer than {} characters",
+                MAX_SERVER_NAME_LENGTH
+            ));
+        }
+
+        if self
+            .bind_addresses
+            .iter()
+            .any(|entry| entry.trim().is_empty())
+        {
+            return Err("Bind addresses can't be empty".into());
+        }
+
+        let heartbeat = self.heartbeat;
+        let too_short = |value: Duration| value < Duration::from_secs(1);
+        if !heartbeat.interval.is_zero()
+            && (too_short(heartbeat.interval) || too_short(heartbeat.timeout))
+        {
+            return Err("Heartbeat interval and timeout must be at least 1 second".into());
+        }
+
+        for rule in &self.rules {
+            rule.validate()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Server-initiated ping policy for WebSocket clients.
+/// - A zero `interval` disables the heartbeat.
+/// - A client is dropped when nothing is heard for `interval + timeout`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// permessage-deflate for WebSocket clients that offer it.
+/// - Messages shorter than `threshold` bytes are sent uncompressed.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Compression {
+    pub enabled: bool,
+    pub threshold: usize,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            threshold: 1024,
         }
     }
 }
 
-#[derive(Default)]
+/// Settings file in the config directory, shared by every clone.
+#[derive(Clone)]
 pub struct Storage {
     settings: Arc<Mutex<Settings>>,
+    report: Arc<Mutex<LoadReport>>,
+    dir: PathBuf,
+}
+
+/// What `Storage::load` couldn't use from the settings file.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct LoadReport {
+    pub problems: Vec<String>,
+    /// Copy of the original file, made before anything in it was dropped.
+    pub backup: Option<PathBuf>,
+}
+
+/// Ignores the command line, see `resolve_config_dir`.
+impl Default for Storage {
+    fn default() -> Self {
+        Self::new(resolve_config_dir(std::iter::empty()))
+    }
+}
+
+/// Where the settings and every other file are kept, the first that applies wins:
+/// 1. `--config-dir <path>` or `--config-dir=<path>` in `args`.
+/// 2. The `VOLUMIZE_CONFIG_DIR` environment variable.
+/// - iOS: `.volumize` in the app's config directory, the same as Tauri's `app_config_dir`.
+///   The sandbox doesn't allow anything else.
+/// 3. Portable mode, from `--portable` or a `portable` file next to the executable:
+///    `volumize-data` next to the executable.
+/// 4. `~/.volumize` when it can be written to.
+/// 5. `volumize` in the platform's config directory, `~/.volumize` when there is none.
+pub fn resolve_config_dir(args: impl IntoIterator<Item = String>) -> PathBuf {
+    config_dir_from(args, std::env::var_os(CONFIG_DIR_ENV))
+}
+
+/// `resolve_config_dir` with the environment variable passed in.
+fn config_dir_from(args: impl IntoIterator<Item = String>, env_dir: Option<OsString>) -> PathBuf {
+    let mut flag_dir = None;
+    let mut portable = false;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--portable" => portable = true,
+            "--config-dir" => flag_dir = args.next().map(PathBuf::from),
+            _ => {
+                if let Some(dir) = arg.strip_prefix("--config-dir=") {
+                    flag_dir = Some(PathBuf::from(dir));
+                }
+            }
+        }
+    }
+
+    let env_dir = env_dir.filter(|dir| !dir.is_empty()).map(PathBuf::from);
+    if let Some(dir) = flag_dir.or(env_dir) {
+        return dir;
+    }
+
+    #[cfg(target_os = "ios")]
+    if let Some(config) = dirs::config_dir() {
+        return config.join(APP_IDENTIFIER).join(".volumize");
+    }
+
+    let exe_dir = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf));
+    if let Some(exe_dir) = exe_dir {
+        if portable || exe_dir.join(PORTABLE_MARKER).is_file() {
+            return exe_dir.join(PORTABLE_DIR);
+        }
+    }
+
+    let home_dir = dirs::home_dir().map(|home| home.join(".volumize"));
+    if let Some(dir) = &home_dir {
+        if is_writable(dir) {
+            return dir.clone();
+        }
+    }
+
+    match (dirs::config_dir(), home_dir) {
+        (Some(config), _) => {
+            let dir = config.join("volumize");
+            eprintln!(
+                "[resolve_config_dir] Home directory not writable, using {}",
+                dir.display()
+            );
+            dir
+        }
+        (None, Some(home)) => home,
+        (None, None) => PathBuf::from(".volumize"),
+    }
+}
+
+/// Creates `dir` when missing and tries writing a file in it.
+fn is_writable(dir: &Path) -> bool {
+    if fs::create_dir_all(dir).is_err() {
+        return false;
+    }
+
+    let probe = dir.join(".write_test");
+    let writable = fs::write(&probe, b"").is_ok();
+    let _ = fs::remove_file(&probe);
+    writable
 }
 
 impl Storage {
-    fn settings_path(&self, app: &AppHandle) -> PathBuf {
-        #[cfg(not(target_os = "ios"))]
-        let os_save_path = app.path().home_dir();
-        #[cfg(target_os = "ios")]
-        let os_save_path = app.path().app_config_dir();
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            settings: Default::default(),
+            report: Default::default(),
+            dir,
+        }
+    }
+
+    /// Created when missing, a failure is logged and left to the caller's own file access.
+    pub fn config_dir(&self) -> PathBuf {
+        if let Err(e) = fs::create_dir_all(&self.dir) {
+            eprintln!(
+                "[Storage::config_dir] Failed to create {}: {}",
+                self.dir.display(),
+                e
+            );
+        }
+        self.dir.clone()
+    }
 
-        let mut dir = os_save_path.expect("Expected app save directory");
-        dir.push(".volumize");
+    fn settings_path(&self) -> PathBuf {
+        self.config_dir().join("settings.json")
+    }
 
-        fs::create_dir_all(&dir).ok();
-        dir.push("settings.json");
-        dir
+    /// Last modification time of the settings file, `None` when it is missing.
+    pub fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(self.settings_path())
+            .and_then(|metadata| metadata.modified())
+            .ok()
     }
 
     pub fn get(&self) -> Settings {
@@ -50,14 +315,155 @@ impl Storage {
         }
     }
 
-    pub fn load(&self, app: &AppHandle) {
-        let settings = match fs::read(&self.settings_path(app)) {
-            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_default(),
-            Err(_) => Settings::default(),
+    /// Looks at the settings in effect without cloning them.
+    pub fn inspect<R>(&self, f: impl FnOnce(&Settings) -> R) -> R {
+        match self.settings.lock() {
+            Ok(settings) => f(&settings),
+            Err(_) => f(&Settings::default()),
+        }
+    }
+
+    /// Problems found by the last `load`.
+    pub fn load_report(&self) -> LoadReport {
+        match self.report.lock() {
+            Ok(report) => report.clone(),
+            Err(e) => e.into_inner().clone(),
+        }
+    }
+
+    /// Reads the settings file, anything that can't be used is reported instead of silently reset.
+    /// - Older files are migrated one version at a time and saved back.
+    /// - Fields that don't parse fall back to their default, the file is backed up first.
+    /// - Saves a new `server_id` right away, it has to stay the same across runs.
+    pub fn load(&self) -> LoadReport {
+        let path = self.settings_path();
+        let mut report = LoadReport::default();
+
+        let (mut settings, mut needs_save) = match fs::read(&path) {
+            Ok(bytes) => self.parse(&bytes, &mut report),
+            Err(e) if e.kind() == ErrorKind::NotFound => (Settings::default(), true),
+            Err(e) => {
+                let problem = format!("Failed to read {}: {}", path.display(), e);
+                report.problems.push(problem);
+                (Settings::default(), false)
+            }
+        };
+
+        if settings.server_id.is_empty() {
+            settings.server_id = Uuid::new_v4().to_string();
+            needs_save = true;
+        }
+
+        if needs_save {
+            if let Err(e) = self.save(&settings) {
+                report
+                    .problems
+                    .push(format!("Failed to save settings: {}", e));
+            }
+        }
+
+        for problem in &report.problems {
+            eprintln!("[Storage::load] {}", problem);
+        }
+
+        self.update(settings);
+        if let Ok(mut value) = self.report.lock() {
+            *value = report.clone();
+        }
+        report
+    }
+
+    /// Returns the settings and whether they differ from what is on disk.
+    fn parse(&self, bytes: &[u8], report: &mut LoadReport) -> (Settings, bool) {
+        let mut object = match serde_json::from_slice::<Value>(bytes) {
+            Ok(Value::Object(object)) => object,
+            Ok(_) => {
+                let problem = "Settings file is not a JSON object".to_string();
+                return self.unusable(bytes, problem, report);
+            }
+            Err(e) => {
+                let problem = format!("Settings file is not valid JSON: {}", e);
+                return self.unusable(bytes, problem, report);
+            }
+        };
+
+        let version = object.get("version").and_then(Value::as_u64).unwrap_or(0) as u32;
+
+        if version > SETTINGS_VERSION {
+            self.backup(bytes, report);
+            report.problems.push(format!(
+                "Settings file is from a newer version ({}), unknown settings are ignored",
+                version
+            ));
+        }
+
+        migrate(&mut object, version);
+
+        if let Ok(settings) = serde_json::from_value(Value::Object(object.clone())) {
+            return (settings, version < SETTINGS_VERSION);
+        }
+
+        // Keep every field that parses on its own, the rest falls back to its default.
+        self.backup(bytes, report);
+
+        let mut merged = match serde_json::to_value(Settings::default()) {
+            Ok(Value::Object(defaults)) => defaults,
+            _ => Map::new(),
         };
+        for (key, value) in object {
+            let mut candidate = merged.clone();
+            candidate.insert(key.clone(), value);
+
+            match serde_json::from_value::<Settings>(Value::Object(candidate.clone())) {
+                Ok(_) => merged = candidate,
+                Err(e) => report
+                    .problems
+                    .push(format!("Invalid `{}`, using the default: {}", key, e)),
+            }
+        }
+
+        let settings = serde_json::from_value(Value::Object(merged)).unwrap_or_default();
+        (settings, true)
+    }
+
+    /// Reads the settings file for a change made while running.
+    /// - Unlike `load` nothing falls back to a default, any problem rejects the whole file.
+    pub fn read(&self) -> Result<Settings, String> {
+        let bytes = fs::read(self.settings_path()).map_err(|e| e.to_string())?;
 
-        if let Ok(mut val) = self.settings.lock() {
-            *val = settings;
+        match serde_json::from_slice::<Value>(&bytes) {
+            Ok(value) => parse_settings(value),
+            Err(e) => Err(format!("Settings file is not valid JSON: {}", e)),
+        }
+    }
+
+    fn unusable(&self, bytes: &[u8], problem: String, report: &mut LoadReport) -> (Settings, bool) {
+        self.backup(bytes, report);
+        report
+            .problems
+            .push(format!("{}, using the defaults", problem));
+        (Settings::default(), true)
+    }
+
+    /// Next to the original, named after the time it was made.
+    fn backup(&self, bytes: &[u8], report: &mut LoadReport) {
+        if report.backup.is_some() {
+            return;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0);
+        let path = self
+            .config_dir()
+            .join(format!("settings.{}.bak.json", timestamp));
+
+        match fs::write(&path, bytes) {
+            Ok(()) => report.backup = Some(path),
+            Err(e) => report
+                .problems
+                .push(format!("Failed to back up the settings file: {}", e)),
         }
     }
 
@@ -67,8 +473,145 @@ impl Storage {
         }
     }
 
-    pub fn save(&self, app: &AppHandle, value: &Settings) -> std::io::Result<()> {
+    pub fn save(&self, value: &Settings) -> std::io::Result<()> {
         let data = serde_json::to_vec_pretty(value)?;
-        fs::write(&self.settings_path(app), data)
+        write_atomic(&self.settings_path(), &data)
+    }
+}
+
+/// Migrates settings of any earlier version, anything that doesn't parse is an error.
+pub fn parse_settings(value: Value) -> Result<Settings, String> {
+    let mut object = match value {
+        Value::Object(object) => object,
+        _ => return Err("Settings are not a JSON object".into()),
+    };
+
+    let version = object.get("version").and_then(Value::as_u64).unwrap_or(0) as u32;
+    if version > SETTINGS_VERSION {
+        return Err(format!("Settings are from a newer version ({})", version));
+    }
+
+    migrate(&mut object, version);
+    serde_json::from_value(Value::Object(object)).map_err(|e| e.to_string())
+}
+
+fn migrate(object: &mut Map<String, Value>, version: u32) {
+    for migration in MIGRATIONS.iter().skip(version as usize) {
+        migration(object);
+    }
+    object.insert("version".into(), SETTINGS_VERSION.into());
+}
+
+/// Written to a temp file and renamed over the original, a crash never leaves half a file.
+pub fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let temp = path.with_extension("json.tmp");
+
+    let mut file = fs::File::create(&temp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+
+    fs::rename(&temp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// Storage in an empty temp directory, with `contents` as the settings file.
+    fn storage(name: &str, contents: &str) -> Storage {
+        let dir =
+            std::env::temp_dir().join(format!("volumize-storage-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        let storage = Storage::new(dir);
+        fs::write(storage.settings_path(), contents).unwrap();
+        storage
+    }
+
+    #[test]
+    fn config_dir_flag_comes_first() {
+        let env = || Some(OsString::from("/tmp/env"));
+
+        let dir = config_dir_from(args(&["--portable", "--config-dir", "/tmp/a"]), env());
+        assert_eq!(dir, PathBuf::from("/tmp/a"));
+
+        let dir = config_dir_from(args(&["--config-dir=/tmp/b", "--verbose"]), env());
+        assert_eq!(dir, PathBuf::from("/tmp/b"));
+
+        // The last one given wins.
+        let dir = config_dir_from(
+            args(&["--config-dir=/tmp/a", "--config-dir", "/tmp/c"]),
+            None,
+        );
+        assert_eq!(dir, PathBuf::from("/tmp/c"));
+    }
+
+    #[test]
+    fn config_dir_from_environment_then_portable() {
+        let from_env = config_dir_from(args(&["--portable"]), Some("/tmp/env".into()));
+        assert_eq!(from_env, PathBuf::from("/tmp/env"));
+
+        // An empty variable counts as unset.
+        let portable = config_dir_from(args(&["--portable"]), Some(OsString::new()));
+        let exe = std::env::current_exe().unwrap();
+        assert_eq!(portable, exe.parent().unwrap().join(PORTABLE_DIR));
+    }
+
+    #[test]
+    fn migrates_unversioned_settings() {
+        let settings = parse_settings(serde_json::json!({ "port_address": 9100 })).unwrap();
+        assert_eq!(settings.version, SETTINGS_VERSION);
+        assert_eq!(settings.port_address, 9100);
+        assert_eq!(settings.compression, Compression::default());
+
+        let mut object = Map::new();
+        migrate(&mut object, 0);
+        assert_eq!(object.get("version"), Some(&SETTINGS_VERSION.into()));
+    }
+
+    #[test]
+    fn parse_settings_rejects_what_it_cant_use() {
+        let newer = serde_json::json!({ "version": SETTINGS_VERSION + 1 });
+        assert!(parse_settings(newer).is_err());
+        assert!(parse_settings(serde_json::json!([1, 2])).is_err());
+        assert!(parse_settings(serde_json::json!({ "port_address": "high" })).is_err());
+    }
+
+    #[test]
+    fn read_rejects_the_whole_file() {
+        let storage = storage("read", r#"{ "version": 1, "server_name": "Desk" }"#);
+        assert_eq!(storage.read().unwrap().server_name, "Desk");
+
+        fs::write(storage.settings_path(), "{ not json").unwrap();
+        assert!(storage.read().is_err());
+
+        // `load` would keep the name and reset the port, `read` takes nothing.
+        let half = r#"{ "server_name": "Desk", "port_address": -1 }"#;
+        fs::write(storage.settings_path(), half).unwrap();
+        assert!(storage.read().is_err());
+
+        let _ = fs::remove_dir_all(&storage.dir);
+    }
+
+    #[test]
+    fn load_keeps_fields_that_parse() {
+        let storage = storage("load", r#"{ "server_name": "Desk", "port_address": -1 }"#);
+
+        let report = storage.load();
+        let settings = storage.get();
+        assert_eq!(settings.server_name, "Desk");
+        assert_eq!(settings.port_address, Settings::default().port_address);
+        assert_eq!(settings.version, SETTINGS_VERSION);
+        assert!(!settings.server_id.is_empty());
+        assert_eq!(report.problems.len(), 1);
+        assert!(report.backup.is_some_and(|backup| backup.is_file()));
+
+        // Saved back migrated, with the new `server_id`.
+        assert_eq!(storage.read().unwrap(), settings);
+
+        let _ = fs::remove_dir_all(&storage.dir);
     }
 }
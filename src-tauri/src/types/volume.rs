@@ -8,7 +8,7 @@ use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 
 use shared_types::{AppIdentifier, AudioApplication, AudioDevice, DeviceIdentifier, VolumePercent};
 
-use crate::types::shared::VolumeResult;
+use crate::types::shared::{Identifier, VolumeResult};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "snake_case")]
@@ -133,8 +133,88 @@ impl VolumeCommand {
 
         String::new()
     }
+
+    /// Commands that change something and don't answer, the ones a rule can run.
+    pub fn is_action(&self) -> bool {
+        matches!(
+            self,
+            VolumeCommand::DeviceSetVolume { .. }
+                | VolumeCommand::DeviceMute { .. }
+                | VolumeCommand::DeviceUnmute { .. }
+                | VolumeCommand::ApplicationSetVolume { .. }
+                | VolumeCommand::ApplicationMute { .. }
+                | VolumeCommand::ApplicationUnmute { .. }
+        )
+    }
+
+    /// Points an action at another app or device of the same kind.
+    /// - Returns false when the kinds don't match, the command is left unchanged.
+    pub fn retarget(&mut self, target: &Identifier) -> bool {
+        match (self, target) {
+            (
+                VolumeCommand::DeviceSetVolume { id, .. }
+                | VolumeCommand::DeviceMute { id, .. }
+                | VolumeCommand::DeviceUnmute { id, .. },
+                Identifier::Device(device),
+            ) => *id = device.clone(),
+            (
+                VolumeCommand::ApplicationSetVolume { id, .. }
+                | VolumeCommand::ApplicationMute { id, .. }
+                | VolumeCommand::ApplicationUnmute { id, .. },
+                Identifier::App(app),
+            ) => *id = *app,
+            _ => return false,
+        }
+        true
+    }
 }
 
+/// Compares what is sent over the wire, reply channels are left out.
+/// - No catch-all on `self`, a new variant has to be added here.
+impl PartialEq for VolumeCommand {
+    fn eq(&self, other: &Self) -> bool {
+        use VolumeCommand::*;
+
+        match self {
+            DeviceGetVolume { request_id, id, .. } => matches!(other,
+                DeviceGetVolume { request_id: r, id: i, .. } if r == request_id && i == id),
+            DeviceSetVolume {
+                request_id,
+                id,
+                volume,
+            } => matches!(other,
+                DeviceSetVolume { request_id: r, id: i, volume: v }
+                    if r == request_id && i == id && v == volume),
+            DeviceMute { request_id, id } => matches!(other,
+                DeviceMute { request_id: r, id: i } if r == request_id && i == id),
+            DeviceUnmute { request_id, id } => matches!(other,
+                DeviceUnmute { request_id: r, id: i } if r == request_id && i == id),
+            GetApplication { request_id, id, .. } => matches!(other,
+                GetApplication { request_id: r, id: i, .. } if r == request_id && i == id),
+            ApplicationGetIcon { request_id, id, .. } => matches!(other,
+                ApplicationGetIcon { request_id: r, id: i, .. } if r == request_id && i == id),
+            ApplicationGetVolume { request_id, id, .. } => matches!(other,
+                ApplicationGetVolume { request_id: r, id: i, .. } if r == request_id && i == id),
+            ApplicationSetVolume {
+                request_id,
+                id,
+                volume,
+            } => matches!(other,
+                ApplicationSetVolume { request_id: r, id: i, volume: v }
+                    if r == request_id && i == id && v == volume),
+            ApplicationMute { request_id, id } => matches!(other,
+                ApplicationMute { request_id: r, id: i } if r == request_id && i == id),
+            ApplicationUnmute { request_id, id } => matches!(other,
+                ApplicationUnmute { request_id: r, id: i } if r == request_id && i == id),
+            GetDeviceApplications { request_id, id, .. } => matches!(other,
+                GetDeviceApplications { request_id: r, id: i, .. } if r == request_id && i == id),
+            GetPlaybackDevices { request_id, .. } => matches!(other,
+                GetPlaybackDevices { request_id: r, .. } if r == request_id),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct VolumeCommandSender {
     pub server: Arc<Mutex<Option<VolumeServer>>>,
 }
@@ -199,3 +279,41 @@ impl VolumeServer {
         thread.map_err(|e| format!("Volume thread panicked during shutdown: {:?}", e))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_commands_ignore_reply_channels() {
+        let get = |sender| VolumeCommand::GetPlaybackDevices {
+            request_id: "1".into(),
+            sender,
+        };
+        assert_eq!(get(unbounded_channel().0), get(unbounded_channel().0));
+
+        let set = VolumeCommand::ApplicationSetVolume {
+            request_id: "1".into(),
+            id: 12,
+            volume: 0.5,
+        };
+        let read_back: VolumeCommand =
+            serde_json::from_value(serde_json::to_value(&set).unwrap()).unwrap();
+        assert_eq!(set, read_back);
+    }
+
+    #[test]
+    fn different_commands_are_not_equal() {
+        let mute = |id: &str| VolumeCommand::DeviceMute {
+            request_id: String::new(),
+            id: id.into(),
+        };
+        let unmute = VolumeCommand::DeviceUnmute {
+            request_id: String::new(),
+            id: "a".into(),
+        };
+
+        assert_ne!(mute("a"), mute("b"));
+        assert_ne!(mute("a"), unmute);
+    }
+}
@@ -25,7 +25,7 @@ export function isDeviceIdentifier(id: Identifier): id is IsDeviceIdentifier {
 }
 
 export type EntityType = "device" | "application";
-export type EntityState = "disconnect" | "created";
+export type EntityState = "disconnect" | "created" | "active" | "default";
 
 const iconPathChange = "iconPathChange";
 const audioVolume = "audioVolume";